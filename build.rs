@@ -0,0 +1,46 @@
+//! Embeds build details for `sumdir version`: the git commit, the target triple and the
+//! version of `infer` that was resolved.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string());
+    println!("cargo:rustc-env=GIT_HASH={}", or_unknown(git_hash));
+    println!(
+        "cargo:rustc-env=TARGET_TRIPLE={}",
+        or_unknown(env::var("TARGET").ok())
+    );
+    println!(
+        "cargo:rustc-env=INFER_VERSION={}",
+        or_unknown(locked_version("infer"))
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
+
+fn or_unknown(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The version of the package `name` in `Cargo.lock`, which isn't there when building from
+/// a published crate.
+fn locked_version(name: &str) -> Option<String> {
+    let lock = fs::read_to_string("Cargo.lock").ok()?;
+    let mut lines = lock.lines();
+    let name_line = format!("name = \"{name}\"");
+    lines.find(|line| *line == name_line)?;
+    let version = lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')?;
+    Some(version.to_string())
+}
@@ -0,0 +1,76 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use indicatif::HumanBytes;
+
+use crate::{ScanOptions, scan_all};
+
+/// How many times each mode scans the target.
+const ITERATIONS: usize = 5;
+
+/// Scans `targets` repeatedly with and without mime detection (read and mmap based) and
+/// prints timing statistics for each, so the cost of content sniffing can be quoted
+/// reproducibly.
+pub fn run(targets: &[PathBuf], options: &ScanOptions, out: &mut dyn Write) -> Result<()> {
+    let modes = [
+        ("extensions only", true, false),
+        ("with mime detection", false, false),
+        ("with mime detection (mmap)", false, true),
+    ];
+    for (label, extensions_only, mmap) in modes {
+        let options = ScanOptions {
+            progress_bar: false,
+            extensions_only,
+            mmap,
+            ..options.clone()
+        };
+        let mut timings = Vec::with_capacity(ITERATIONS);
+        let mut files = 0;
+        let mut bytes = 0;
+        for _ in 0..ITERATIONS {
+            let started = Instant::now();
+            let report = scan_all(targets, &options)?;
+            timings.push(started.elapsed());
+            files = report.file_count();
+            bytes = report.size;
+        }
+
+        let median = median(&mut timings);
+        let mean = timings.iter().sum::<Duration>() / ITERATIONS as u32;
+        let seconds = median.as_secs_f64().max(f64::EPSILON);
+        writeln!(
+            out,
+            "{label}: mean {mean:.2?}, median {median:.2?}, {:.0} files/s, {}/s",
+            files as f64 / seconds,
+            HumanBytes((bytes as f64 / seconds) as u64)
+        )?;
+    }
+    Ok(())
+}
+
+fn median(timings: &mut [Duration]) -> Duration {
+    timings.sort();
+    let middle = timings.len() / 2;
+    if timings.len().is_multiple_of(2) {
+        (timings[middle - 1] + timings[middle]) / 2
+    } else {
+        timings[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median() {
+        let ms = Duration::from_millis;
+        assert_eq!(median(&mut [ms(5), ms(1), ms(3)]), ms(3));
+        assert_eq!(
+            median(&mut [ms(4), ms(1), ms(3), ms(2)]),
+            Duration::from_micros(2500)
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+
+use crate::{OutputFormat, ScanArgs};
+
+const FILE_NAME: &str = "sumdir.toml";
+
+/// Defaults for `scan` flags, read from `sumdir.toml`. Keys are named after the flags they
+/// stand in for, e.g. `exclude-dir = ["node_modules"]`; unknown keys are an error, so a typo
+/// doesn't silently do nothing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    output: Option<OutputFormat>,
+    mime: Option<bool>,
+    exclude_dir: Option<Vec<String>>,
+    exclude_hidden_dirs: Option<bool>,
+    follow_symlinks: Option<bool>,
+    threads: Option<usize>,
+    top: Option<usize>,
+    precision: Option<usize>,
+}
+
+/// Loads the first config file found: `sumdir.toml` in the current directory, then in
+/// `$XDG_CONFIG_HOME` (`~/.config` if that isn't set). Without one, nothing is overridden.
+pub fn load() -> Result<Config> {
+    match search_paths().into_iter().find(|path| path.is_file()) {
+        Some(path) => {
+            let contents =
+                fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+            parse(&contents, &path)
+        }
+        None => Ok(Config::default()),
+    }
+}
+
+fn search_paths() -> Vec<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    [
+        Some(PathBuf::from(FILE_NAME)),
+        config_home.map(|dir| dir.join(FILE_NAME)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+fn parse(contents: &str, path: &Path) -> Result<Config> {
+    toml::from_str(contents).with_context(|| format!("invalid config file {:?}", path))
+}
+
+impl Config {
+    /// Fills in the settings of `args` that were neither given on the command line nor
+    /// through the environment, so flags always win over the file.
+    ///
+    /// A switch turned on in the file can't be turned off again for a single run, as
+    /// switches have no `--no-` form.
+    pub fn apply(self, args: &mut ScanArgs, matches: &ArgMatches) {
+        let unset = |id: &str| {
+            !matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        if let Some(output) = self.output
+            && unset("output")
+        {
+            args.output = output;
+        }
+        // `--both` already shows mimetypes, next to the extensions.
+        if let Some(mime) = self.mime
+            && unset("mime")
+            && !args.both
+        {
+            args.mime = mime;
+        }
+        if let Some(dirs) = self.exclude_dir
+            && unset("exclude_dir")
+        {
+            args.exclude_dir = dirs.into_iter().map(Into::into).collect();
+        }
+        if let Some(exclude_hidden_dirs) = self.exclude_hidden_dirs
+            && unset("exclude_hidden_dirs")
+        {
+            args.exclude_hidden_dirs = exclude_hidden_dirs;
+        }
+        if let Some(follow_symlinks) = self.follow_symlinks
+            && unset("follow_symlinks")
+        {
+            args.follow_symlinks = follow_symlinks;
+        }
+        if let Some(threads) = self.threads
+            && unset("threads")
+        {
+            args.threads = threads;
+        }
+        if let Some(top) = self.top
+            && unset("top")
+        {
+            args.top = Some(top);
+        }
+        if let Some(precision) = self.precision
+            && unset("precision")
+        {
+            args.precision = precision;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+    use crate::Cli;
+
+    #[test]
+    fn test_config_fills_in_flags_not_given() {
+        let config = || {
+            parse(
+                "output = \"json\"\nexclude-dir = [\"node_modules\"]\nthreads = 4\n",
+                Path::new(FILE_NAME),
+            )
+            .expect("failed to parse config")
+        };
+        let scan_args = |argv: &[&str]| {
+            let matches = Cli::command()
+                .try_get_matches_from(argv)
+                .expect("failed to parse arguments");
+            let mut args = Cli::from_arg_matches(&matches)
+                .expect("failed to parse arguments")
+                .scan
+                .expect("expected scan arguments");
+            config().apply(&mut args, &matches);
+            args
+        };
+        let _env = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let defaults = scan_args(&["sumdir", "dir"]);
+        let overridden = scan_args(&["sumdir", "dir", "-o", "csv", "--threads", "2"]);
+
+        assert!(matches!(defaults.output, OutputFormat::Json));
+        assert_eq!(defaults.exclude_dir, vec!["node_modules"]);
+        assert_eq!(defaults.threads, 4);
+        assert!(matches!(overridden.output, OutputFormat::Csv));
+        assert_eq!(overridden.threads, 2);
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_keys() {
+        let parsed = parse("max-dpeth = 3\n", Path::new(FILE_NAME));
+
+        assert!(parsed.is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use walkdir::WalkDir;
+
+use crate::hash::{HashAlgo, hash_file, hash_file_edges};
+use crate::{friendly_bytes, report_skipped};
+
+#[derive(clap::Args)]
+pub struct DedupArgs {
+    /// Directory to search for duplicates
+    target: PathBuf,
+
+    /// Checksum algorithm used to compare file contents; md5 and sha1 are not
+    /// collision-resistant, so crafted files could pass for duplicates
+    #[arg(long, value_enum, default_value_t = HashAlgo::Blake3)]
+    hash_algo: HashAlgo,
+
+    /// Only hash the first and last 4 KiB of each file; matches are probable duplicates
+    #[arg(long, default_value_t = false)]
+    quick_dedup: bool,
+
+    /// Confirm --quick-dedup matches by hashing the full contents
+    #[arg(long, default_value_t = false, requires = "quick_dedup")]
+    verify: bool,
+}
+
+/// How much of each file is hashed when comparing contents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Strategy {
+    /// Hash the whole file.
+    Full,
+    /// Hash only the ends of the file, see [`hash_file_edges`].
+    Quick,
+    /// Hash the ends first, then the whole file for the candidates that still match.
+    QuickVerified,
+}
+
+/// Files that share the same content.
+#[derive(Debug, PartialEq)]
+struct DuplicateGroup {
+    digest: String,
+    size: u64,
+    paths: Vec<PathBuf>,
+    /// Only the ends of the files were compared.
+    probable: bool,
+}
+
+impl DuplicateGroup {
+    /// Bytes that could be freed by keeping only one copy.
+    fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+pub fn run(args: &DedupArgs) -> Result<()> {
+    let strategy = match (args.quick_dedup, args.verify) {
+        (false, _) => Strategy::Full,
+        (true, false) => Strategy::Quick,
+        (true, true) => Strategy::QuickVerified,
+    };
+    let mut errors = Vec::new();
+    let groups = find_duplicates(&args.target, args.hash_algo, strategy, &mut errors);
+    let mut stdout = io::stdout().lock();
+    for group in &groups {
+        let label = if group.probable { "probable " } else { "" };
+        writeln!(
+            stdout,
+            "{} ({} {label}copies of {})",
+            group.digest,
+            group.paths.len(),
+            friendly_bytes(group.size, crate::DEFAULT_PRECISION)
+        )?;
+        for path in &group.paths {
+            writeln!(stdout, "  {}", path.display())?;
+        }
+    }
+    let duplicates: usize = groups.iter().map(|group| group.paths.len() - 1).sum();
+    let wasted: u64 = groups.iter().map(DuplicateGroup::wasted).sum();
+    writeln!(
+        stdout,
+        "{duplicates} {}duplicate files, {} reclaimable",
+        if strategy == Strategy::Quick {
+            "probable "
+        } else {
+            ""
+        },
+        friendly_bytes(wasted, crate::DEFAULT_PRECISION)
+    )?;
+    report_skipped(&errors)
+}
+
+/// Groups the files under `target` by content, biggest waste first.
+///
+/// Only files that share their size with another file get hashed, which rules out most
+/// files without reading them. Empty files are ignored. Entries that can't be read or
+/// hashed are left out and their errors added to `errors`.
+fn find_duplicates(
+    target: &Path,
+    algo: HashAlgo,
+    strategy: Strategy,
+    errors: &mut Vec<anyhow::Error>,
+) -> Vec<DuplicateGroup> {
+    let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for entry in WalkDir::new(target).sort_by_file_name() {
+        let entry = match entry.context("failed to read entry") {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let size = match entry
+            .metadata()
+            .with_context(|| format!("failed to read metadata for {:?}", entry.path()))
+        {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        if size > 0 {
+            by_size.entry(size).or_default().push(entry.into_path());
+        }
+    }
+
+    let mut groups = Vec::new();
+    for (size, paths) in by_size {
+        if paths.len() < 2 {
+            continue;
+        }
+        let candidates = match strategy {
+            Strategy::Full => group_by_digest(paths, |path| hash_file(path, algo), errors),
+            Strategy::Quick | Strategy::QuickVerified => {
+                group_by_digest(paths, |path| hash_file_edges(path, algo), errors)
+            }
+        };
+        for (digest, paths) in candidates {
+            if strategy == Strategy::QuickVerified {
+                let confirmed = group_by_digest(paths, |path| hash_file(path, algo), errors);
+                groups.extend(confirmed.into_iter().map(|(digest, paths)| DuplicateGroup {
+                    digest,
+                    size,
+                    paths,
+                    probable: false,
+                }));
+            } else {
+                groups.push(DuplicateGroup {
+                    digest,
+                    size,
+                    paths,
+                    probable: strategy == Strategy::Quick,
+                });
+            }
+        }
+    }
+    groups
+        .into_iter()
+        .sorted_by(|a, b| b.wasted().cmp(&a.wasted()))
+        .collect()
+}
+
+/// Buckets `paths` by the digest `hash` computes, keeping only buckets with several files.
+/// Files that fail to hash are left out and their errors added to `errors`.
+fn group_by_digest(
+    paths: Vec<PathBuf>,
+    hash: impl Fn(&Path) -> Result<String>,
+    errors: &mut Vec<anyhow::Error>,
+) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut by_digest: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in paths {
+        match hash(&path) {
+            Ok(digest) => by_digest.entry(digest).or_default().push(path),
+            Err(e) => errors.push(e),
+        }
+    }
+    by_digest.retain(|_, paths| paths.len() > 1);
+    by_digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicates() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join("a.txt"), "same").expect("failed to write file");
+        std::fs::write(dir.join("b.txt"), "same").expect("failed to write file");
+        std::fs::write(dir.join("c.txt"), "diff").expect("failed to write file");
+        std::fs::write(dir.join("d.txt"), "").expect("failed to write file");
+        std::fs::write(dir.join("e.txt"), "").expect("failed to write file");
+
+        let mut errors = Vec::new();
+        let groups = find_duplicates(&dir, HashAlgo::Sha256, Strategy::Full, &mut errors);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].paths, vec![dir.join("a.txt"), dir.join("b.txt")]);
+        assert_eq!(groups[0].wasted(), 4);
+        assert!(!groups[0].probable);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_find_duplicates_skips_unreadable_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        for name in ["a.txt", "b.txt", "locked.txt"] {
+            std::fs::write(dir.join(name), "same").expect("failed to write file");
+        }
+        std::fs::set_permissions(dir.join("locked.txt"), PermissionsExt::from_mode(0o000))
+            .expect("failed to set permissions");
+        // Permissions don't stop root, so there is nothing to skip then.
+        if std::fs::File::open(dir.join("locked.txt")).is_ok() {
+            return;
+        }
+
+        let mut errors = Vec::new();
+        let groups = find_duplicates(&dir, HashAlgo::Blake3, Strategy::Full, &mut errors);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].paths, vec![dir.join("a.txt"), dir.join("b.txt")]);
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:#}", errors[0]).contains("locked.txt"));
+    }
+
+    #[test]
+    fn test_quick_dedup_is_confirmed_by_verify() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        let mut contents = vec![b'a'; 3 * crate::hash::EDGE_SIZE as usize];
+        std::fs::write(dir.join("a.bin"), &contents).expect("failed to write file");
+        std::fs::write(dir.join("b.bin"), &contents).expect("failed to write file");
+        contents[crate::hash::EDGE_SIZE as usize + 1] = b'c';
+        std::fs::write(dir.join("c.bin"), &contents).expect("failed to write file");
+
+        let mut errors = Vec::new();
+        let quick = find_duplicates(&dir, HashAlgo::Sha256, Strategy::Quick, &mut errors);
+        let verified =
+            find_duplicates(&dir, HashAlgo::Sha256, Strategy::QuickVerified, &mut errors);
+
+        assert_eq!(quick.len(), 1);
+        assert_eq!(quick[0].paths.len(), 3);
+        assert!(quick[0].probable);
+        assert_eq!(verified.len(), 1);
+        assert_eq!(
+            verified[0].paths,
+            vec![dir.join("a.bin"), dir.join("b.bin")]
+        );
+    }
+}
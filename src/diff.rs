@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::{DEFAULT_PRECISION, Report, ScanOptions, export, friendly_bytes, scan};
+
+#[derive(clap::Args)]
+pub struct DiffArgs {
+    /// Directory, or scan saved with `--export`, to compare against
+    old: PathBuf,
+
+    /// Directory, or scan saved with `--export`, to compare
+    new: PathBuf,
+
+    /// Compare by detected mimetype instead of by extension
+    #[arg(short, long, default_value_t = false)]
+    mime: bool,
+}
+
+/// How the number of files in one group changed between the two directories.
+#[derive(Debug, PartialEq)]
+struct Change {
+    key: String,
+    old: i32,
+    new: i32,
+}
+
+pub fn run(args: &DiffArgs) -> Result<()> {
+    let options = ScanOptions {
+        extensions_only: !args.mime,
+        ..ScanOptions::default()
+    };
+    let old = load_or_scan(&args.old, &options)?;
+    let new = load_or_scan(&args.new, &options)?;
+    let (old_groups, new_groups) = if args.mime {
+        (&old.mimetypes, &new.mimetypes)
+    } else {
+        (&old.extensions, &new.extensions)
+    };
+
+    let mut stdout = io::stdout().lock();
+    let old_files: i32 = old_groups.values().sum();
+    let new_files: i32 = new_groups.values().sum();
+    writeln!(
+        stdout,
+        "files: {old_files} -> {new_files} ({:+})",
+        new_files - old_files
+    )?;
+    let size_change = if new.size >= old.size {
+        format!(
+            "+{}",
+            friendly_bytes(new.size - old.size, DEFAULT_PRECISION)
+        )
+    } else {
+        format!(
+            "-{}",
+            friendly_bytes(old.size - new.size, DEFAULT_PRECISION)
+        )
+    };
+    writeln!(
+        stdout,
+        "size: {} -> {} ({size_change})",
+        friendly_bytes(old.size, DEFAULT_PRECISION),
+        friendly_bytes(new.size, DEFAULT_PRECISION)
+    )?;
+    for change in changes(old_groups, new_groups) {
+        writeln!(
+            stdout,
+            "{}: {} -> {} ({:+})",
+            change.key,
+            change.old,
+            change.new,
+            change.new - change.old
+        )?;
+    }
+    Ok(())
+}
+
+/// Describes each number of `new` that differs from `old` by more than `tolerance` percent:
+/// the file count, the size, and the count of every extension (mimetype with `mime`).
+/// Something appearing out of nothing always counts as drift.
+pub fn drift(old: &Report, new: &Report, mime: bool, tolerance: f64) -> Vec<String> {
+    let (old_groups, new_groups) = if mime {
+        (&old.mimetypes, &new.mimetypes)
+    } else {
+        (&old.extensions, &new.extensions)
+    };
+    let old_files: i32 = old_groups.values().sum();
+    let new_files: i32 = new_groups.values().sum();
+    let mut drift = Vec::new();
+    if let Some(change) = relative_change(f64::from(old_files), f64::from(new_files), tolerance) {
+        drift.push(format!("files: {old_files} -> {new_files} ({change})"));
+    }
+    if let Some(change) = relative_change(old.size as f64, new.size as f64, tolerance) {
+        drift.push(format!(
+            "size: {} -> {} ({change})",
+            friendly_bytes(old.size, DEFAULT_PRECISION),
+            friendly_bytes(new.size, DEFAULT_PRECISION)
+        ));
+    }
+    for change in changes(old_groups, new_groups) {
+        let relative = relative_change(f64::from(change.old), f64::from(change.new), tolerance);
+        if let Some(relative) = relative {
+            drift.push(format!(
+                "{}: {} -> {} ({relative})",
+                change.key, change.old, change.new
+            ));
+        }
+    }
+    drift
+}
+
+/// The change from `old` to `new` as a signed percentage like `+12.5%`, if it is larger
+/// than `tolerance` percent.
+fn relative_change(old: f64, new: f64, tolerance: f64) -> Option<String> {
+    if old == 0.0 {
+        return (new != 0.0).then(|| "new".to_string());
+    }
+    let pct = (new - old) * 100.0 / old;
+    (pct.abs() > tolerance).then(|| format!("{pct:+.1}%"))
+}
+
+/// Reads `path` as an exported scan if it is a file, and scans it if it is a directory.
+fn load_or_scan(path: &Path, options: &ScanOptions) -> Result<Report> {
+    if path.is_file() {
+        export::load(path)
+    } else {
+        scan(path.to_path_buf(), options)
+    }
+}
+
+/// Groups whose count differs between `old` and `new`, in key order.
+fn changes(old: &BTreeMap<String, i32>, new: &BTreeMap<String, i32>) -> Vec<Change> {
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .map(|key| Change {
+            key: key.clone(),
+            old: old.get(key).copied().unwrap_or_default(),
+            new: new.get(key).copied().unwrap_or_default(),
+        })
+        .filter(|change| change.old != change.new)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drift_respects_tolerance() {
+        let old = Report {
+            size: 1000,
+            extensions: BTreeMap::from([("png".to_string(), 10), ("txt".to_string(), 10)]),
+            ..Report::default()
+        };
+        let new = Report {
+            size: 1040,
+            extensions: BTreeMap::from([
+                ("png".to_string(), 12),
+                ("txt".to_string(), 10),
+                ("exe".to_string(), 1),
+            ]),
+            ..Report::default()
+        };
+
+        assert_eq!(
+            drift(&old, &new, false, 5.0),
+            vec![
+                "files: 20 -> 23 (+15.0%)",
+                "exe: 0 -> 1 (new)",
+                "png: 10 -> 12 (+20.0%)"
+            ]
+        );
+        assert_eq!(drift(&old, &new, false, 20.0), vec!["exe: 0 -> 1 (new)"]);
+        assert!(drift(&old, &old, false, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_changes_lists_only_differences() {
+        let old = BTreeMap::from([("png".to_string(), 2), ("txt".to_string(), 1)]);
+        let new = BTreeMap::from([("png".to_string(), 2), ("jpg".to_string(), 3)]);
+
+        let changes = changes(&old, &new);
+
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    key: "jpg".to_string(),
+                    old: 0,
+                    new: 3
+                },
+                Change {
+                    key: "txt".to_string(),
+                    old: 1,
+                    new: 0
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::Report;
+
+/// Bumped whenever a change to `Report` makes older exports unreadable.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// On-disk form of an exported scan; `schema_version` is checked before this is parsed.
+#[derive(Deserialize)]
+struct Export {
+    report: Report,
+}
+
+/// Borrowed counterpart of [`Export`], so saving doesn't need to clone the report.
+#[derive(Serialize)]
+struct ExportRef<'a> {
+    schema_version: u32,
+    report: &'a Report,
+}
+
+/// Writes the complete `report` to `path` as JSON.
+pub fn save(path: &Path, report: &Report) -> Result<()> {
+    let export = ExportRef {
+        schema_version: SCHEMA_VERSION,
+        report,
+    };
+    let contents = serde_json::to_string(&export).context("failed to serialize report")?;
+    fs::write(path, contents).with_context(|| format!("failed to write {:?}", path))
+}
+
+/// Reads a report written by [`save`], refusing exports from another schema version.
+pub fn load(path: &Path) -> Result<Report> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    let version: serde_json::Value =
+        serde_json::from_str(&contents).with_context(|| format!("failed to parse {:?}", path))?;
+    match version
+        .get("schema_version")
+        .and_then(serde_json::Value::as_u64)
+    {
+        Some(version) if version == u64::from(SCHEMA_VERSION) => {}
+        Some(version) => bail!(
+            "{:?} uses export schema version {version}, expected {SCHEMA_VERSION}",
+            path
+        ),
+        None => bail!("{:?} is not a sumdir export", path),
+    }
+    let export: Export =
+        serde_json::from_str(&contents).with_context(|| format!("failed to parse {:?}", path))?;
+    Ok(export.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("scan.json");
+        let report = Report {
+            size: 42,
+            ..Report::default()
+        };
+
+        save(&path, &report).expect("failed to export");
+        let loaded = load(&path).expect("failed to import");
+
+        assert_eq!(loaded.size, 42);
+    }
+
+    #[test]
+    fn test_load_rejects_other_schema_versions() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("scan.json");
+        fs::write(&path, r#"{"schema_version": 999, "report": {}}"#).expect("failed to write file");
+
+        let error = load(&path).expect_err("import should fail");
+
+        assert!(error.to_string().contains("schema version 999"));
+    }
+}
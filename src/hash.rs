@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::Digest;
+
+/// Checksum algorithm used by `dedup` and `manifest`.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum HashAlgo {
+    /// Fastest; the default for `dedup`
+    Blake3,
+    /// The default for `manifest`, verifiable with `sha256sum -c`
+    Sha256,
+    /// Not collision-resistant; don't rely on it against tampering
+    Sha1,
+    /// Not collision-resistant; don't rely on it against tampering
+    Md5,
+}
+
+/// Running state of one of the supported hashers.
+enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+    Sha1(sha1::Sha1),
+    Md5(md5::Md5),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Blake3 => Hasher::Blake3(Box::default()),
+            HashAlgo::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgo::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+            HashAlgo::Md5 => Hasher::Md5(md5::Md5::new()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha1(hasher) => hasher.update(bytes),
+            Hasher::Md5(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Sha256(hasher) => hex(&hasher.finalize()),
+            Hasher::Sha1(hasher) => hex(&hasher.finalize()),
+            Hasher::Md5(hasher) => hex(&hasher.finalize()),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Bytes read at a time by [`hash_file`].
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Hashes the contents of the file at `path`, returning the digest as lowercase hex.
+pub fn hash_file(path: &Path, algo: HashAlgo) -> Result<String> {
+    hash_file_chunked(path, algo, CHUNK_SIZE)
+}
+
+/// Like [`hash_file`], but streams the file through the hasher `chunk_size` bytes at a time,
+/// so memory use doesn't grow with the file.
+pub fn hash_file_chunked(path: &Path, algo: HashAlgo, chunk_size: usize) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let mut hasher = Hasher::new(algo);
+    let mut buffer = vec![0; chunk_size.max(1)];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("failed to read {:?}", path))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize_hex())
+}
+
+/// Bytes read from each end of a file by [`hash_file_edges`].
+pub const EDGE_SIZE: u64 = 4096;
+
+/// Hashes the file's size together with its first and last [`EDGE_SIZE`] bytes.
+///
+/// Much cheaper than [`hash_file`] on large files, but files that only differ in the middle
+/// get the same digest, so a match is only a probable duplicate.
+pub fn hash_file_edges(path: &Path, algo: HashAlgo) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let size = file
+        .metadata()
+        .with_context(|| format!("failed to read metadata for {:?}", path))?
+        .len();
+    let mut hasher = Hasher::new(algo);
+    hasher.update(&size.to_le_bytes());
+    let head_len = size.min(EDGE_SIZE);
+    let tail_len = size.saturating_sub(EDGE_SIZE).min(EDGE_SIZE);
+    let mut buffer = vec![0; (head_len + tail_len) as usize];
+    let (head, tail) = buffer.split_at_mut(head_len as usize);
+    file.read_exact(head)
+        .and_then(|()| file.seek(SeekFrom::Start(size - tail_len)))
+        .and_then(|_| file.read_exact(tail))
+        .with_context(|| format!("failed to read {:?}", path))?;
+    hasher.update(&buffer);
+    Ok(hasher.finalize_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_file_known_digests() {
+        let path = Path::new("testdata/plain.txt");
+        let expected = [
+            (
+                HashAlgo::Sha256,
+                "f4094f79a8979ace80cc375ab6c1dc640cceee36249ce03928d7e54f7ad66234",
+            ),
+            (HashAlgo::Sha1, "a80338ff32a9b2d4550be8ceb93921f1ce73b343"),
+            (HashAlgo::Md5, "d7e288e2c268b456c3892c3f297dad3a"),
+        ];
+        for (algo, digest) in expected {
+            assert_eq!(hash_file(path, algo).expect("hash failed"), digest);
+        }
+    }
+
+    #[test]
+    fn test_hash_file_across_chunk_boundaries() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("large");
+        std::fs::write(&path, "sumdir".repeat(30_000)).expect("failed to write file");
+        let expected = "862d88c71329a81f30a3fd3657e91a9d8d39ad323189ebbc48d3d0ab617d562a";
+
+        for chunk_size in [1, 7, 4096, CHUNK_SIZE] {
+            let digest =
+                hash_file_chunked(&path, HashAlgo::Sha256, chunk_size).expect("hash failed");
+            assert_eq!(digest, expected, "chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_hash_file_blake3_empty() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("empty");
+        std::fs::write(&path, "").expect("failed to write file");
+
+        let digest = hash_file(&path, HashAlgo::Blake3).expect("hash failed");
+
+        assert_eq!(
+            digest,
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+    }
+
+    #[test]
+    fn test_hash_file_edges_ignores_the_middle() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        let mut contents = vec![b'a'; 3 * EDGE_SIZE as usize];
+        std::fs::write(dir.join("a"), &contents).expect("failed to write file");
+        contents[EDGE_SIZE as usize + 1] = b'b';
+        std::fs::write(dir.join("b"), &contents).expect("failed to write file");
+        std::fs::write(dir.join("short"), "short").expect("failed to write file");
+
+        let edges_a = hash_file_edges(&dir.join("a"), HashAlgo::Sha256).expect("hash failed");
+        let edges_b = hash_file_edges(&dir.join("b"), HashAlgo::Sha256).expect("hash failed");
+        let full_a = hash_file(&dir.join("a"), HashAlgo::Sha256).expect("hash failed");
+        let full_b = hash_file(&dir.join("b"), HashAlgo::Sha256).expect("hash failed");
+        let short = hash_file_edges(&dir.join("short"), HashAlgo::Sha256);
+
+        assert_eq!(edges_a, edges_b);
+        assert_ne!(full_a, full_b);
+        assert!(short.is_ok());
+    }
+}
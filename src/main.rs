@@ -1,447 +1,7406 @@
 use std::fs::File;
-use std::io::Read;
-use std::{collections::BTreeMap, path::PathBuf};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::panic::AssertUnwindSafe;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use walkdir::WalkDir;
 
-use anyhow::{Context, Result};
-use clap::Parser;
-use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
-use itertools::Itertools;
+use anyhow::{Context, Result, bail};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use itertools::{Either, Itertools};
+use memmap2::{Mmap, MmapOptions};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
 
-#[derive(Clone, Default, clap::ValueEnum)]
+mod benchmark;
+mod config;
+mod dedup;
+mod diff;
+mod export;
+mod hash;
+mod manifest;
+mod owner;
+mod resume;
+mod version;
+mod watch;
+
+#[derive(Debug, Clone, Default, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 enum OutputFormat {
     #[default]
     Text,
     Csv,
     Json,
+    Prometheus,
+    /// A self-contained page with a sortable table
+    Html,
+}
+
+impl OutputFormat {
+    /// The format a file named like `path` is expected to hold, judged by its extension;
+    /// `.gz` is looked through, so `report.json.gz` is JSON.
+    fn for_path(path: &Path) -> Option<Self> {
+        let path = match path.extension() {
+            Some(ext) if ext == "gz" => Path::new(path.file_stem()?),
+            _ => path,
+        };
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "txt" => Some(Self::Text),
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            "prom" => Some(Self::Prometheus),
+            "html" | "htm" => Some(Self::Html),
+            _ => None,
+        }
+    }
+}
+
+/// How timestamps such as `--recent`'s modification times are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+enum TimeFormat {
+    /// `2024-01-02T15:04:05Z`
+    #[default]
+    Iso8601,
+    /// `3 days ago`
+    Relative,
+    /// Seconds since the Unix epoch
+    Unix,
+}
+
+/// Which timestamp `--cold-threshold` judges the age of a file by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+enum ColdBy {
+    /// Time of the last modification
+    #[default]
+    Mtime,
+    /// Time of the last access; unreliable on filesystems mounted with `noatime`
+    Atime,
+}
+
+/// A value column of the CSV output, picked with `--csv-columns`.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum CsvColumn {
+    /// Number of files in the group
+    Count,
+    /// Total size of the group in bytes (needs --mime)
+    Bytes,
+    /// The group's share of all files, in percent
+    Pct,
 }
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
-    #[arg(required = true)]
-    target: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Arguments of a bare `sumdir <path>`, which is shorthand for `sumdir scan <path>`.
+    #[command(flatten)]
+    scan: Option<ScanArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Summarize a directory by file type (the default when no subcommand is given)
+    Scan(Box<ScanArgs>),
+    /// Compare the file type breakdown of two directories
+    Diff(diff::DiffArgs),
+    /// Find files with identical content
+    Dedup(dedup::DedupArgs),
+    /// Print a checksum manifest in `sha256sum` format
+    Manifest(manifest::ManifestArgs),
+    /// Print the version, git commit, target and key dependency versions of this build
+    Version(version::VersionArgs),
+}
+
+#[derive(clap::Args)]
+struct ScanArgs {
+    /// Directory to scan; may be a glob pattern such as `'~/projects/*/src'`
+    #[arg(required_unless_present = "import")]
+    target: Option<PathBuf>,
 
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
 
-    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    #[arg(short, long, value_enum, env = "SUMDIR_OUTPUT", default_value_t = OutputFormat::Text)]
     output: OutputFormat,
 
     #[arg(short, long, default_value_t = false)]
     mime: bool,
 
+    /// Show the extension and the mimetype breakdown one after the other (text, CSV and
+    /// JSON)
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["mime", "extensions_only", "mimetypes_only", "oneline", "flatten"]
+    )]
+    both: bool,
+
+    /// Only tally extensions and never open a file to detect its mimetype
+    #[arg(long, default_value_t = false, conflicts_with_all = ["mime", "mime_from_ext"])]
+    extensions_only: bool,
+
+    /// Only tally mimetypes and skip the extension tally; implies --mime
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["extensions_only", "oneline", "warn_if", "suggest_aliases"]
+    )]
+    mimetypes_only: bool,
+
     #[arg(short, long, default_value_t = true)]
     progress_bar: bool,
+
+    /// Keep running and re-scan whenever files under the target change; what the first scan
+    /// finds, e.g. a --warn-if violation, is only warned about instead of ending the process
+    #[arg(long, default_value_t = false)]
+    watch: bool,
+
+    /// Stop recording individual errors after N; further errors are only counted
+    #[arg(long, value_name = "N")]
+    max_errors: Option<usize>,
+
+    /// Normalize extensions and mimetypes to Unicode NFC before grouping
+    #[arg(long, default_value_t = false)]
+    normalize_unicode: bool,
+
+    /// Emit JSON as a single flat object with dotted keys (requires `--output json`)
+    #[arg(long, default_value_t = false)]
+    flatten: bool,
+
+    /// Emit the JSON group objects in alphabetical key order rather than by count, so
+    /// reports diff cleanly (requires `--output json`)
+    #[arg(long, default_value_t = false)]
+    sort_keys: bool,
+
+    /// Abort with a non-zero exit code on the first error instead of collecting errors
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// List the N directories with the most files directly inside them
+    #[arg(long, value_name = "N")]
+    fat_dirs: Option<usize>,
+
+    /// List the N directories with the most entries (files and folders) anywhere below them
+    #[arg(long, value_name = "N")]
+    largest_dirs: Option<usize>,
+
+    /// List the N most common file names (`index.js`, `__init__.py`), with how many there are
+    /// and their total size
+    #[arg(long, value_name = "N")]
+    top_names: Option<usize>,
+
+    /// Show how the files of the N most common extensions spread over directory depths, as
+    /// a sparkline (`▁▃█▂`) per extension; plain numbers where the locale isn't UTF-8
+    #[arg(long, value_name = "N")]
+    sparkline: Option<usize>,
+
+    /// Derive mimetypes from file extensions instead of reading file contents
+    #[arg(long, default_value_t = false)]
+    mime_from_ext: bool,
+
+    /// Record content that can't be identified as `unknown` rather than
+    /// `application/octet-stream`
+    #[arg(long, default_value_t = false)]
+    no_mime_fallback: bool,
+
+    /// Tally mimetypes by their major type only (`image`, `text`, ...)
+    #[arg(long, default_value_t = false)]
+    collapse_mime_subtypes: bool,
+
+    /// Memory-map files for mime sniffing instead of reading them
+    #[arg(long, default_value_t = false)]
+    mmap: bool,
+
+    /// How much of the start of each file is read to sniff its mimetype [default: 8KiB]
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    sniff_bytes: Option<u64>,
+
+    /// Keep reading files whose content isn't recognized yet, doubling the window each time,
+    /// until SIZE has been looked at (e.g. `1MiB`); formats whose magic lies further in are
+    /// then still found, while recognized files cost no more than before
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    sniff_max_bytes: Option<u64>,
+
+    /// Group files without an extension under the extension their content suggests
+    #[arg(long, default_value_t = false)]
+    merge_extensionless_by_magic: bool,
+
+    /// Count files without an extension under `(none)`, names ending in a dot (`foo.`) under
+    /// `(empty)` and dotfiles (`.bashrc`) under `(dotfile)`, instead of lumping them together
+    #[arg(long, default_value_t = false)]
+    report_empty_extensions_separately: bool,
+
+    /// Group files whose name matches this regex under a key built from --group-replace,
+    /// instead of under their extension
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new, requires = "group_replace")]
+    group_regex: Option<Regex>,
+
+    /// Template for the group key of files matched by --group-regex, e.g. `$1-*.log`
+    #[arg(long, value_name = "TEMPLATE", requires = "group_regex")]
+    group_replace: Option<String>,
+
+    /// Warn about files whose mimetype sniffing takes longer than MS milliseconds
+    #[arg(long, value_name = "MS")]
+    warn_slow_files: Option<u64>,
+
+    /// Flag JPEG, PNG and GIF files that lack their format's end marker, which usually means
+    /// an interrupted download; reads each such file's last bytes as well
+    #[arg(long, default_value_t = false, conflicts_with_all = ["mime_from_ext", "extensions_only"])]
+    check_integrity: bool,
+
+    /// Rename files whose content contradicts their extension (a PNG named `.jpg`) to the
+    /// extension the content suggests; files without an extension are left alone
+    #[arg(long, default_value_t = false, conflicts_with_all = ["mime_from_ext", "extensions_only"])]
+    fix_extensions: bool,
+
+    /// With --fix-extensions, only print the `mv` commands that would rename the files
+    #[arg(long, default_value_t = false, requires = "fix_extensions")]
+    dry_run: bool,
+
+    /// Print a `still scanning` line to stderr every SECONDS, even without a terminal, so
+    /// long unattended runs don't look hung
+    #[arg(long, value_name = "SECONDS")]
+    heartbeat: Option<u64>,
+
+    /// Sniff file contents on N threads; the walk itself stays single-threaded
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    /// Stop scanning once this many bytes have been accounted for (e.g. `10GiB`)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    limit_bytes: Option<u64>,
+
+    /// Count the target directory itself in the folder total, like `du` does
+    #[arg(long, default_value_t = false)]
+    include_root: bool,
+
+    /// Follow symlinks, so a link to a directory counts as a folder and is descended into;
+    /// by default links are tallied as files of their own
+    #[arg(long, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Skip directories named NAME, along with everything inside (repeatable); with
+    /// --follow-symlinks, links resolving into such a directory are skipped too
+    #[arg(long, value_name = "NAME")]
+    exclude_dir: Vec<OsString>,
+
+    /// Skip directories whose name starts with a dot (`.git`, `.cache`), along with everything
+    /// inside; hidden files elsewhere are still counted
+    #[arg(long, default_value_t = false)]
+    exclude_hidden_dirs: bool,
+
+    /// Only count files whose extension is listed in FILE, one per line; blank lines and
+    /// `#` comments are ignored, as is case
+    #[arg(long, value_name = "FILE", value_parser = load_ext_whitelist)]
+    ext_whitelist_from: Option<BTreeSet<String>>,
+
+    /// Print to stderr, for every entry, whether it was counted or which rule skipped it;
+    /// the report itself is unchanged
+    #[arg(long, default_value_t = false)]
+    explain: bool,
+
+    /// Add the size of what symlinks point to into the size totals, as sumdir used to,
+    /// instead of leaving links out of them
+    #[arg(long, default_value_t = false)]
+    count_symlinks_as_files: bool,
+
+    /// Break the size down by top-level child directory, shallow and recursive
+    #[arg(long, default_value_t = false)]
+    by_dir: bool,
+
+    /// Count files by size on a logarithmic scale, in buckets of powers of --histogram-base
+    #[arg(long, default_value_t = false)]
+    size_histogram: bool,
+
+    /// Base of the --size-histogram buckets, e.g. 10 for 1-9 B, 10-99 B, 100-999 B, ...
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u64).range(2..), requires = "size_histogram")]
+    histogram_base: u64,
+
+    /// Use exactly K --size-histogram buckets, the last one taking every larger file;
+    /// by default there are as many as the largest file needs
+    #[arg(long, value_name = "K", value_parser = clap::value_parser!(u64).range(1..), requires = "size_histogram")]
+    histogram_buckets: Option<u64>,
+
+    /// Break the extension counts down by directory, attributing files deeper than N levels
+    /// to their ancestor N levels below the target
+    #[arg(long, value_name = "N")]
+    rollup_depth: Option<usize>,
+
+    /// Break the size down by the user owning the files, largest first (Unix only)
+    #[arg(long, default_value_t = false)]
+    by_owner: bool,
+
+    /// List names in the same directory that differ only by case, such as `Readme.md` and
+    /// `README.md`, which collide on case-insensitive filesystems
+    #[arg(long, default_value_t = false)]
+    case_collisions: bool,
+
+    /// Only show the N largest groups and roll the rest up into one row
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// Label of the row that collects the groups cut off by --top
+    #[arg(long, value_name = "STR", default_value = DEFAULT_OTHER_LABEL)]
+    other_label: String,
+
+    /// Exit with code 1 (4 with --detailed-exit-codes) if there are more than MAX files with
+    /// extension EXT (repeatable), e.g. `--warn-if exe:0`
+    #[arg(long, value_name = "EXT:MAX", value_parser = parse_count_threshold)]
+    warn_if: Vec<CountThreshold>,
+
+    /// Compare the scan to one saved with --export, exiting with code 1 (4 with
+    /// --detailed-exit-codes) if the file count, size or count of any group drifted from it
+    /// by more than --tolerance
+    #[arg(long, value_name = "FILE", conflicts_with = "watch")]
+    compare_to: Option<PathBuf>,
+
+    /// How far, in percent, --compare-to lets each number drift before failing
+    #[arg(long, value_name = "PCT", default_value_t = 0.0, value_parser = parse_percentage, requires = "compare_to")]
+    tolerance: f64,
+
+    /// Tell apart in the exit code why a scan didn't come out clean: 3 when entries couldn't
+    /// be read, 4 when a --warn-if or --tolerance threshold was exceeded, 5 when the scan
+    /// was cut short
+    #[arg(long, default_value_t = false)]
+    detailed_exit_codes: bool,
+
+    /// Point out extensions that are spellings of the same type, like jpg and jpeg, on stderr
+    #[arg(long, default_value_t = false)]
+    suggest_aliases: bool,
+
+    /// Print only `<files>f <folders>d <size>` on one line, e.g. for a shell prompt; a format
+    /// from `SUMDIR_OUTPUT` or `sumdir.toml` is ignored
+    #[arg(long, default_value_t = false, conflicts_with = "mime")]
+    oneline: bool,
+
+    /// Don't echo the scanned paths at the top of the report (`Scan of ...:` in text, a
+    /// `# target:` comment in CSV, `targets` in JSON); in CSV, also leave out the column
+    /// header row, so several outputs can be concatenated
+    #[arg(long, default_value_t = false)]
+    no_header: bool,
+
+    /// Leave out only the column header row of the CSV output, keeping the `# target:`
+    /// comment
+    #[arg(long, default_value_t = false)]
+    no_csv_header: bool,
+
+    /// Value columns of the CSV output, in order, e.g. `count,bytes,pct`; defaults to
+    /// `count`, plus the size with --mime
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+    csv_columns: Option<Vec<CsvColumn>>,
+
+    /// Draw a bar next to each row of the text report, scaled to the largest count and
+    /// sized to fit the terminal
+    #[arg(long, default_value_t = false)]
+    bars: bool,
+
+    /// Leave out the summary lines of the text report and print only the `key: count` rows
+    #[arg(long, default_value_t = false, conflicts_with = "oneline")]
+    no_summary: bool,
+
+    /// List each path that couldn't be read, with the reason, after the text report
+    #[arg(long, default_value_t = false)]
+    list_errors: bool,
+
+    /// In the --list-errors text, show each distinct error message once, with how many paths
+    /// hit it and a few of them; JSON still lists every error
+    #[arg(long, default_value_t = false, requires = "list_errors")]
+    dedupe_report: bool,
+
+    /// Print only the paths that couldn't be read, each terminated by a NUL byte
+    #[arg(long, default_value_t = false)]
+    print0_errors: bool,
+
+    /// Number of decimals in human-readable sizes
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_PRECISION)]
+    precision: usize,
+
+    /// How to print timestamps
+    #[arg(long, value_enum, default_value_t = TimeFormat::default())]
+    time_format: TimeFormat,
+
+    /// Measure relative times from TIMESTAMP (`2024-03-01T12:00:00Z` or Unix seconds)
+    /// instead of the current time, so ages come out the same on every run
+    #[arg(long, value_name = "TIMESTAMP", env = "SUMDIR_NOW", value_parser = parse_timestamp)]
+    now: Option<SystemTime>,
+
+    /// List the N most recently modified files after the summary
+    #[arg(long, value_name = "N")]
+    recent: Option<usize>,
+
+    /// Sum up the files not touched for AGE (`90d`, `12h`, `1y`, ...) as cold data, measured
+    /// back from --now
+    #[arg(long, value_name = "AGE", value_parser = parse_age)]
+    cold_threshold: Option<Duration>,
+
+    /// Which timestamp --cold-threshold goes by
+    #[arg(long, value_enum, default_value_t = ColdBy::default(), requires = "cold_threshold")]
+    cold_by: ColdBy,
+
+    /// Report paths relative to DIR where they lie under it; others are left as they are
+    #[arg(long, value_name = "DIR")]
+    relative_to: Option<PathBuf>,
+
+    /// Drop the first N components of reported paths, like `tar --strip-components`; paths
+    /// that aren't that deep are reduced to their file name
+    #[arg(long, value_name = "N")]
+    strip_prefix_components: Option<usize>,
+
+    /// Add a per-file list (path, extension, mimetype, size) to JSON output
+    #[arg(long, default_value_t = false)]
+    include_files: bool,
+
+    /// Refuse --include-files for targets with more than N files
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_INCLUDE_FILES_LIMIT)]
+    include_files_limit: usize,
+
+    /// Save the complete scan result to FILE for later use with --import or `sumdir diff`
+    #[arg(long, value_name = "FILE")]
+    export: Option<PathBuf>,
+
+    /// Report on a scan saved with --export instead of walking a directory
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["target", "watch", "benchmark", "resume_file"]
+    )]
+    import: Option<PathBuf>,
+
+    /// Periodically save progress to FILE and pick up from there when re-run; not with
+    /// --follow-symlinks or --case-collisions
+    #[arg(long, value_name = "FILE")]
+    resume_file: Option<PathBuf>,
+
+    /// Write the report to FILE instead of stdout
+    #[arg(long, value_name = "FILE", conflicts_with = "watch")]
+    output_file: Option<PathBuf>,
+
+    /// Compress --output-file with gzip as it is written, adding `.gz` to its name unless
+    /// it already ends in it
+    #[arg(long, default_value_t = false, requires = "output_file")]
+    gzip: bool,
+
+    /// Additionally write the report as JSON to FILE
+    #[arg(long, value_name = "FILE")]
+    also_json: Option<PathBuf>,
+
+    /// Additionally write the report as CSV to FILE
+    #[arg(long, value_name = "FILE")]
+    also_csv: Option<PathBuf>,
+
+    /// Time repeated scans with and without mime detection instead of printing a report
+    #[arg(long, hide = true, default_value_t = false)]
+    benchmark: bool,
+}
+
+/// A predicate on the entries of a walk, for [`ScanOptions::filter`].
+#[derive(Clone)]
+struct EntryFilter(Arc<dyn Fn(&walkdir::DirEntry) -> bool + Send + Sync>);
+
+impl std::fmt::Debug for EntryFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EntryFilter(..)")
+    }
 }
 
+/// Settings that influence how a target is walked and tallied.
 #[derive(Debug, Default, Clone)]
+struct ScanOptions {
+    progress_bar: bool,
+    max_errors: Option<usize>,
+    normalize_unicode: bool,
+    strict: bool,
+    /// Skip content sniffing entirely; only the extension tally is filled in.
+    extensions_only: bool,
+    /// Skip the extension tally; only the mimetype tally is filled in.
+    mimetypes_only: bool,
+    /// Treat the root of the walk as an entry of its own instead of skipping it.
+    include_root: bool,
+    /// Descend into symlinked directories and classify links by what they point to.
+    follow_symlinks: bool,
+    /// Names of directories that are skipped entirely.
+    exclude_dirs: Vec<OsString>,
+    /// Skip directories whose name starts with a dot.
+    exclude_hidden_dirs: bool,
+    /// Decides which entries are walked, after `exclude_dirs` and `exclude_hidden_dirs` have
+    /// had their say; returning false for a directory prunes its whole subtree.
+    filter: Option<EntryFilter>,
+    /// Lowercased extensions that are counted; files with any other are ignored.
+    ext_whitelist: Option<BTreeSet<String>>,
+    /// Say on stderr why every entry was counted or skipped.
+    explain: bool,
+    /// Size symlinks by their target like regular files, rather than excluding them.
+    count_symlinks_as_files: bool,
+    /// Sniff content through a memory map rather than a buffered read.
+    mmap: bool,
+    /// Bytes looked at first when sniffing; [`SNIFF_WINDOW`] if unset.
+    sniff_bytes: Option<usize>,
+    /// Bytes looked at, at most, when the first window isn't recognized; no more than
+    /// `sniff_bytes` if unset.
+    sniff_max_bytes: Option<usize>,
+    /// Look mimetypes up from the extension alone, without opening the file.
+    mime_from_ext: bool,
+    /// Tell unidentified content apart from `application/octet-stream` when sniffing.
+    no_mime_fallback: bool,
+    collapse_mime_subtypes: bool,
+    /// Stop the walk once the report's size reaches this many bytes.
+    limit_bytes: Option<u64>,
+    /// Group matching file names by a rewritten name rather than their extension.
+    group_regex: Option<GroupRegex>,
+    /// Sniff extensionless files to file them under the extension their content suggests.
+    merge_extensionless_by_magic: bool,
+    /// Tell apart the ways of having no extension, see [`empty_extension_bucket`].
+    split_empty_extensions: bool,
+    /// Record files whose mimetype sniffing takes longer than this.
+    warn_slow_files: Option<Duration>,
+    /// Check sniffed images for their end marker, recording those without as suspect.
+    check_integrity: bool,
+    /// Note the extension that sniffed content suggests for files whose extension stands
+    /// for something else.
+    suggest_extensions: bool,
+    /// How often to report progress on stderr as plain log lines.
+    heartbeat: Option<Duration>,
+    /// Checkpoint file for resuming an interrupted scan.
+    resume_file: Option<PathBuf>,
+    /// Keep track of this many most recently modified files.
+    recent: Option<usize>,
+    /// Tally files last touched before this time as cold data.
+    cold_before: Option<SystemTime>,
+    /// The timestamp `cold_before` is compared to.
+    cold_by: ColdBy,
+    /// Keep a record of every file, failing the scan once there are more than this many.
+    include_files: Option<usize>,
+    /// Process files on this many threads; 0 and 1 both mean on the walking thread.
+    threads: usize,
+    /// Bucket sibling names case-insensitively to find names that only differ by case.
+    case_collisions: bool,
+    /// Count the entries below every directory, for `--largest-dirs`.
+    dir_entry_counts: bool,
+    /// Count the files directly inside every directory, for `--fat-dirs`.
+    dir_file_counts: bool,
+    /// Sum up the sizes of the target's immediate subdirectories, for `--by-dir`.
+    top_level_sizes: bool,
+    /// Sum up the bytes owned by every uid, for `--by-owner`.
+    owner_sizes: bool,
+    /// Count files and their bytes by file name, for `--top-names`.
+    name_counts: bool,
+    /// Count the files of every extension by depth, for `--sparkline`.
+    depth_counts: bool,
+    /// Tally extensions per directory, down to this many levels below the target.
+    rollup_depth: Option<usize>,
+    /// Count files into size buckets.
+    histogram: Option<HistogramScheme>,
+}
+
+impl ScanOptions {
+    /// The settings that change what a report counts, by their flag's name. Settings that
+    /// only affect how the scan runs, like `--threads`, are left out.
+    fn effective(&self) -> Vec<(&'static str, serde_json::Value)> {
+        use serde_json::json;
+        vec![
+            ("follow_symlinks", json!(self.follow_symlinks)),
+            (
+                "exclude_dir",
+                json!(
+                    self.exclude_dirs
+                        .iter()
+                        .map(|dir| dir.to_string_lossy())
+                        .collect::<Vec<_>>()
+                ),
+            ),
+            ("exclude_hidden_dirs", json!(self.exclude_hidden_dirs)),
+            ("ext_whitelist", json!(self.ext_whitelist)),
+            ("include_root", json!(self.include_root)),
+            (
+                "count_symlinks_as_files",
+                json!(self.count_symlinks_as_files),
+            ),
+            ("extensions_only", json!(self.extensions_only)),
+            ("mimetypes_only", json!(self.mimetypes_only)),
+            ("mime_from_ext", json!(self.mime_from_ext)),
+            ("no_mime_fallback", json!(self.no_mime_fallback)),
+            ("sniff_bytes", json!(self.sniff_bytes)),
+            ("sniff_max_bytes", json!(self.sniff_max_bytes)),
+            ("collapse_mime_subtypes", json!(self.collapse_mime_subtypes)),
+            ("normalize_unicode", json!(self.normalize_unicode)),
+            (
+                "merge_extensionless_by_magic",
+                json!(self.merge_extensionless_by_magic),
+            ),
+            (
+                "report_empty_extensions_separately",
+                json!(self.split_empty_extensions),
+            ),
+            (
+                "group_regex",
+                json!(
+                    self.group_regex
+                        .as_ref()
+                        .map(|group| group.pattern.as_str())
+                ),
+            ),
+            (
+                "group_replace",
+                json!(self.group_regex.as_ref().map(|group| &group.replacement)),
+            ),
+            ("cold_before", json!(self.cold_before.map(format_timestamp))),
+            (
+                "cold_by",
+                json!(self.cold_before.map(|_| match self.cold_by {
+                    ColdBy::Mtime => "mtime",
+                    ColdBy::Atime => "atime",
+                })),
+            ),
+            ("limit_bytes", json!(self.limit_bytes)),
+            ("max_errors", json!(self.max_errors)),
+        ]
+    }
+}
+
+/// How `--size-histogram` buckets file sizes: bucket 0 holds sizes below `base`, and
+/// bucket `i` those from `base^i` up to `base^(i + 1)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HistogramScheme {
+    base: u64,
+    /// Fixed number of buckets, the last of which has no upper bound; unbounded if unset.
+    buckets: Option<usize>,
+}
+
+impl HistogramScheme {
+    /// Index of the bucket a file of `size` bytes falls into.
+    fn bucket(&self, size: u64) -> usize {
+        let mut index = 0;
+        let mut upper = self.base;
+        while size >= upper && self.buckets.is_none_or(|buckets| index + 1 < buckets) {
+            index += 1;
+            match upper.checked_mul(self.base) {
+                Some(next) => upper = next,
+                None => break,
+            }
+        }
+        index
+    }
+
+    /// The sizes bucket `index` covers, from the lower bound up to, but excluding, the upper
+    /// one. The last of a fixed number of buckets has no upper bound.
+    fn bounds(&self, index: usize) -> (u64, Option<u64>) {
+        let power = |exp: usize| {
+            u32::try_from(exp)
+                .ok()
+                .and_then(|exp| self.base.checked_pow(exp))
+        };
+        let lower = if index == 0 {
+            0
+        } else {
+            power(index).unwrap_or(u64::MAX)
+        };
+        let upper = if self.buckets == Some(index + 1) {
+            None
+        } else {
+            power(index + 1)
+        };
+        (lower, upper)
+    }
+}
+
+/// A `--group-regex` pattern along with its `--group-replace` template.
+#[derive(Debug, Clone)]
+struct GroupRegex {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl GroupRegex {
+    /// The group key for `file_name`, or `None` when the pattern doesn't match it.
+    fn key(&self, file_name: &str) -> Option<String> {
+        self.pattern.is_match(file_name).then(|| {
+            self.pattern
+                .replace(file_name, &self.replacement)
+                .into_owned()
+        })
+    }
+}
+
+/// A `--warn-if` limit on the number of files with one extension.
+#[derive(Debug, Clone, PartialEq)]
+struct CountThreshold {
+    extension: String,
+    max: u32,
+}
+
+impl CountThreshold {
+    /// Describes how `report` exceeds this threshold, if it does.
+    fn violation(&self, report: &Report) -> Option<String> {
+        let count = report
+            .extensions
+            .get(&self.extension)
+            .copied()
+            .unwrap_or_default();
+        (i64::from(count) > i64::from(self.max)).then(|| {
+            format!(
+                "{count} files with extension {:?}, at most {} allowed",
+                self.extension, self.max
+            )
+        })
+    }
+}
+
+/// How a run ended, as told by the exit code. Usage errors exit with 2, from clap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Clean,
+    /// The run couldn't be completed, or a report couldn't be written.
+    Failed,
+    /// Some entries couldn't be read, so the report leaves them out.
+    ScanErrors,
+    /// A `--warn-if` threshold was exceeded, or the scan drifted from `--compare-to`.
+    ThresholdExceeded,
+    /// The scan stopped early, e.g. at `--limit-bytes`, so the report is partial.
+    Truncated,
+}
+
+impl Outcome {
+    /// The outcome of a completed scan, the most pressing reason first.
+    fn of_scan(report: &Report, threshold_exceeded: bool) -> Self {
+        if threshold_exceeded {
+            Self::ThresholdExceeded
+        } else if report.truncated {
+            Self::Truncated
+        } else if report.error_count() > 0 {
+            Self::ScanErrors
+        } else {
+            Self::Clean
+        }
+    }
+
+    /// What the outcome amounts to without `--detailed-exit-codes`: only an exceeded
+    /// threshold fails the run.
+    fn coarse(self) -> Self {
+        match self {
+            Self::Failed | Self::ThresholdExceeded => Self::Failed,
+            Self::Clean | Self::ScanErrors | Self::Truncated => Self::Clean,
+        }
+    }
+
+    fn code(self) -> i32 {
+        match self {
+            Self::Clean => 0,
+            Self::Failed => 1,
+            Self::ScanErrors => 3,
+            Self::ThresholdExceeded => 4,
+            Self::Truncated => 5,
+        }
+    }
+}
+
+/// Prints the entries a subcommand had to skip, one `error:` line each, and fails if there
+/// were any, so a partial result never exits cleanly.
+fn report_skipped(errors: &[anyhow::Error]) -> Result<()> {
+    for error in errors {
+        eprintln!("error: {error:#}");
+    }
+    if !errors.is_empty() {
+        bail!(
+            "skipped {} that couldn't be read",
+            counted(errors.len(), "path")
+        );
+    }
+    Ok(())
+}
+
+/// Ends the process with the exit code of `outcome`.
+fn exit(outcome: Outcome) -> ! {
+    std::process::exit(outcome.code())
+}
+
+/// Parses a `--warn-if` value such as `exe:0`; a leading dot on the extension is ignored.
+fn parse_count_threshold(input: &str) -> Result<CountThreshold> {
+    let (extension, max) = input
+        .rsplit_once(':')
+        .with_context(|| format!("expected EXT:MAX, got {input:?}"))?;
+    let max = max
+        .parse()
+        .with_context(|| format!("invalid maximum {max:?} in {input:?}"))?;
+    Ok(CountThreshold {
+        extension: extension.trim_start_matches('.').to_string(),
+        max,
+    })
+}
+
+/// Parses a percentage such as `5` or `2.5`; negative ones make no sense as a tolerance.
+fn parse_percentage(input: &str) -> Result<f64> {
+    let pct: f64 = input
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .with_context(|| format!("invalid percentage {input:?}"))?;
+    if !pct.is_finite() || pct < 0.0 {
+        bail!("invalid percentage {input:?}, expected a number of at least 0");
+    }
+    Ok(pct)
+}
+
+/// Reads the extension list of `--ext-whitelist-from`. Extensions may be written with or
+/// without a leading dot and are lowercased, so matching ignores case.
+fn load_ext_whitelist(path: &str) -> Result<BTreeSet<String>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read {path:?}"))?;
+    Ok(parse_ext_list(&contents))
+}
+
+fn parse_ext_list(contents: &str) -> BTreeSet<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.trim_start_matches('.').to_lowercase())
+        .collect()
+}
+
+/// Default for `--include-files-limit`; every record is held in memory until the end.
+const DEFAULT_INCLUDE_FILES_LIMIT: usize = 10_000;
+
+/// Label of the row that absorbs everything cut off by `--top`, unless overridden.
+const DEFAULT_OTHER_LABEL: &str = "other";
+
+/// Default for `--precision`, e.g. `1.32 KiB`.
+const DEFAULT_PRECISION: usize = 2;
+
+/// Line width `--bars` fills when stdout isn't a terminal.
+const DEFAULT_LINE_WIDTH: usize = 80;
+
+/// Bars never shrink below this, however long the keys are.
+const MIN_BAR_WIDTH: usize = 10;
+
+/// The levels of a `--sparkline`, from a few files to the most at any depth.
+const SPARK_GLYPHS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Settings that influence how a finished report is rendered.
+#[derive(Debug, Clone)]
+struct DisplayOptions {
+    /// The scanned paths, echoed at the top of the report; left empty to leave them out.
+    targets: Vec<PathBuf>,
+    format: OutputFormat,
+    use_mime: bool,
+    /// Follow the extension rows with the mimetype rows, see `--both`.
+    both: bool,
+    flatten: bool,
+    /// Order the JSON group objects by key instead of by count.
+    sort_keys: bool,
+    fat_dirs: Option<usize>,
+    largest_dirs: Option<usize>,
+    top_names: Option<usize>,
+    sparkline: Option<usize>,
+    /// Print the counts behind `--sparkline` instead of glyphs the terminal can't render.
+    plain_sparklines: bool,
+    by_dir: bool,
+    histogram: Option<HistogramScheme>,
+    rollup_depth: Option<usize>,
+    by_owner: bool,
+    case_collisions: bool,
+    /// Only show this many groups and roll the rest up into one row.
+    top: Option<usize>,
+    other_label: String,
+    list_errors: bool,
+    /// Group the listed errors by message rather than listing each one.
+    dedupe_errors: bool,
+    include_files: bool,
+    recent: Option<usize>,
+    /// Show the cold data summed up by `--cold-threshold`.
+    cold_data: bool,
+    /// Decimals shown in human-readable sizes.
+    precision: usize,
+    time_format: TimeFormat,
+    /// Reference time for relative timestamps; the current time if unset.
+    now: Option<SystemTime>,
+    slow_files: bool,
+    suspect_files: bool,
+    /// Base that reported paths are made relative to, where they lie under it.
+    relative_to: Option<PathBuf>,
+    /// Leading path components dropped from reported paths, after `relative_to`.
+    strip_prefix_components: Option<usize>,
+    /// Replace the report with a single compact summary line.
+    oneline: bool,
+    /// Print the text rows without the summary lines above them.
+    no_summary: bool,
+    /// Print the CSV rows without the column header row above them.
+    no_csv_header: bool,
+    /// Line width to fit text rows with bars into; `None` prints no bars.
+    bars: Option<usize>,
+    /// Columns of the CSV output after the key; `None` keeps the classic layout.
+    csv_columns: Option<Vec<CsvColumn>>,
+    /// Replace the report with a NUL-separated list of failing paths, for `xargs -0`.
+    print0_errors: bool,
+    /// The settings the report was scanned with, echoed in JSON so it describes itself.
+    scan_options: Option<ScanOptions>,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            format: OutputFormat::default(),
+            use_mime: false,
+            both: false,
+            flatten: false,
+            sort_keys: false,
+            fat_dirs: None,
+            largest_dirs: None,
+            top_names: None,
+            sparkline: None,
+            plain_sparklines: false,
+            by_dir: false,
+            histogram: None,
+            rollup_depth: None,
+            by_owner: false,
+            case_collisions: false,
+            top: None,
+            other_label: DEFAULT_OTHER_LABEL.to_string(),
+            list_errors: false,
+            dedupe_errors: false,
+            include_files: false,
+            recent: None,
+            cold_data: false,
+            precision: DEFAULT_PRECISION,
+            time_format: TimeFormat::default(),
+            now: None,
+            slow_files: false,
+            suspect_files: false,
+            relative_to: None,
+            strip_prefix_components: None,
+            oneline: false,
+            no_summary: false,
+            no_csv_header: false,
+            bars: None,
+            csv_columns: None,
+            print0_errors: false,
+            scan_options: None,
+        }
+    }
+}
+
+impl DisplayOptions {
+    /// How `path` should appear in the output, honouring `relative_to` and
+    /// `strip_prefix_components`.
+    fn path<'a>(&self, path: &'a Path) -> &'a Path {
+        let path = self
+            .relative_to
+            .as_deref()
+            .and_then(|base| path.strip_prefix(base).ok())
+            .unwrap_or(path);
+        match self.strip_prefix_components {
+            Some(count) => strip_components(path, count),
+            None => path,
+        }
+    }
+
+    /// How `time` should appear in the output, honouring `time_format`.
+    fn time(&self, time: SystemTime) -> String {
+        let now = self.now.unwrap_or_else(SystemTime::now);
+        format_time(time, self.time_format, now)
+    }
+
+    /// Like [`DisplayOptions::time`], but as a JSON value: a number for Unix time, a string
+    /// otherwise.
+    fn json_time(&self, time: SystemTime) -> String {
+        match self.time_format {
+            TimeFormat::Unix => self.time(time),
+            TimeFormat::Iso8601 | TimeFormat::Relative => {
+                format!("\"{}\"", json_escape(&self.time(time)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct ScanError {
     path: PathBuf,
     message: String,
+    #[serde(default)]
+    kind: ScanErrorKind,
+}
+
+/// Rough cause of a [`ScanError`], so a deep tree hitting the OS path length limit isn't
+/// mistaken for a permissions problem.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ScanErrorKind {
+    #[default]
+    Other,
+    PermissionDenied,
+    PathTooLong,
+}
+
+impl ScanErrorKind {
+    fn of_io(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            // ENAMETOOLONG on Unix, ERROR_FILENAME_EXCED_RANGE on Windows
+            io::ErrorKind::InvalidFilename => Self::PathTooLong,
+            _ => Self::Other,
+        }
+    }
+
+    /// Classifies by the first I/O error in the chain of `error`.
+    fn of(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map_or(Self::Other, Self::of_io)
+    }
+
+    /// Machine-readable name, as used in JSON output.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::PermissionDenied => "permission_denied",
+            Self::PathTooLong => "path_too_long",
+        }
+    }
+
+    /// Human-readable tag for text output; plain errors go without one.
+    fn label(self) -> Option<&'static str> {
+        match self {
+            Self::Other => None,
+            Self::PermissionDenied => Some("permission denied"),
+            Self::PathTooLong => Some("path too long"),
+        }
+    }
+}
+
+/// One file of the scan, kept for `--include-files`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct FileRecord {
+    path: PathBuf,
+    ext: String,
+    /// Absent when the scan didn't look at mimetypes.
+    mime: Option<String>,
+    size: u64,
+}
+
+/// A file's modification time, as tracked for `--recent`.
+///
+/// Ordered by `modified` first, so the oldest entry is the one a bounded heap evicts.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct RecentFile {
+    modified: SystemTime,
+    path: PathBuf,
+    size: u64,
+}
+
+/// A file whose mimetype sniffing exceeded `--warn-slow-files`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SlowFile {
+    path: PathBuf,
+    elapsed: Duration,
+}
+
+/// Paths shown per message by `--dedupe-report`.
+const ERROR_EXAMPLES: usize = 3;
+
+/// A file whose content contradicts its extension.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct MisnamedFile {
+    path: PathBuf,
+    /// The extension the content suggests, as `infer` names it.
+    suggested_ext: String,
+}
+
+/// Size of one top-level child directory of the target.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct DirSizes {
+    /// Bytes of the files directly inside the directory.
+    shallow_size: u64,
+    /// Bytes of all files in the directory's subtree.
+    recursive_size: u64,
+    /// Number of files in the directory's subtree.
+    files: usize,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct Report {
     extensions: BTreeMap<String, i32>,
     mimetypes: BTreeMap<String, i32>,
+    mimetype_sizes: BTreeMap<String, u64>,
     folders: Vec<PathBuf>,
+    /// Number of files directly inside each directory, keyed by the directory's path; only
+    /// filled in with `ScanOptions::dir_file_counts`.
+    dir_file_counts: BTreeMap<PathBuf, usize>,
+    /// Sizes of the target's immediate subdirectories, keyed by their path; only filled in
+    /// with `ScanOptions::top_level_sizes`.
+    top_level_dirs: BTreeMap<PathBuf, DirSizes>,
+    /// Every file seen, in walk order; only filled in with `ScanOptions::include_files`.
+    file_records: Vec<FileRecord>,
+    /// Min-heap of the newest files seen, bounded by `ScanOptions::recent`.
+    recent_files: BinaryHeap<Reverse<RecentFile>>,
+    /// Files that were slow to sniff, in walk order.
+    slow_files: Vec<SlowFile>,
     size: u64,
     errors: Vec<ScanError>,
+    suppressed_errors: usize,
+    /// Files whose extension, as found on disk, is all uppercase (`JPG`).
+    uppercase_ext_files: usize,
+    /// Files whose extension mixes upper- and lowercase letters (`Jpg`).
+    mixedcase_ext_files: usize,
+    /// Set when the scan stopped early, so the numbers only cover part of the target.
+    truncated: bool,
+    /// Symlinks that were tallied but left out of `size`, so their targets aren't counted
+    /// twice.
+    #[serde(default)]
+    excluded_symlinks: usize,
+    /// FIFOs, sockets and device nodes that were tallied but left out of `size`.
+    #[serde(default)]
+    excluded_special_files: usize,
+    /// Paths skipped because, through followed symlinks, they lead to a file that was already
+    /// counted under another path.
+    #[serde(default)]
+    collapsed_link_paths: usize,
+    /// Files last touched before `ScanOptions::cold_before`, and their bytes.
+    #[serde(default)]
+    cold_files: usize,
+    #[serde(default)]
+    cold_size: u64,
+    /// Bytes of the regular files owned by each uid; only filled in with
+    /// `ScanOptions::owner_sizes`, and stays empty on platforms without uids.
+    #[serde(default)]
+    owner_sizes: BTreeMap<u32, u64>,
+    /// Sets of sibling paths whose names only differ by case, each sorted.
+    #[serde(default)]
+    case_collisions: Vec<Vec<PathBuf>>,
+    /// Files whose content was read to detect their mimetype.
+    #[serde(default)]
+    sniffed_files: usize,
+    /// Bytes read from disk to detect mimetypes, summed over `sniffed_files`.
+    #[serde(default)]
+    bytes_read: u64,
+    /// Number of entries anywhere below each directory, keyed by the directory's path; only
+    /// filled in with `ScanOptions::dir_entry_counts`.
+    #[serde(default)]
+    dir_entry_counts: BTreeMap<PathBuf, usize>,
+    /// Images that end without their format's end marker, so are probably truncated, in walk
+    /// order.
+    #[serde(default)]
+    suspect_files: Vec<PathBuf>,
+    /// Extension counts per directory, with deeper files rolled up into their ancestor at
+    /// `ScanOptions::rollup_depth`.
+    #[serde(default)]
+    rollup: BTreeMap<PathBuf, BTreeMap<String, i32>>,
+    /// Files whose content contradicts their extension, in walk order; only filled in with
+    /// `ScanOptions::suggest_extensions`.
+    #[serde(default)]
+    misnamed_files: Vec<MisnamedFile>,
+    /// Number of files of each extension by depth, index 0 holding the target's direct
+    /// children; only filled in with `ScanOptions::depth_counts`.
+    #[serde(default)]
+    ext_depths: BTreeMap<String, Vec<usize>>,
+    /// Number of files in each `ScanOptions::histogram` bucket, by bucket index; trailing
+    /// empty buckets are left out.
+    #[serde(default)]
+    size_histogram: Vec<usize>,
+    /// Number of files and their bytes by file name; only filled in with
+    /// `ScanOptions::name_counts`.
+    #[serde(default)]
+    file_names: BTreeMap<String, (i32, u64)>,
 }
 
 impl Report {
-    fn display(&self, format: &OutputFormat, use_mime: bool) {
-        let data = if use_mime {
+    /// Records an error, keeping at most `max_errors` of them around.
+    ///
+    /// Errors beyond the limit are only counted so memory stays bounded on badly broken trees.
+    fn push_error(&mut self, error: ScanError, max_errors: Option<usize>) {
+        if max_errors.is_some_and(|max| self.errors.len() >= max) {
+            self.suppressed_errors += 1;
+        } else {
+            self.errors.push(error);
+        }
+    }
+
+    /// Remembers `file` if it is among the `limit` newest seen so far.
+    fn push_recent(&mut self, file: RecentFile, limit: usize) {
+        self.recent_files.push(Reverse(file));
+        if self.recent_files.len() > limit {
+            self.recent_files.pop();
+        }
+    }
+
+    /// Drops all but the `limit` newest files, e.g. after merging another report in.
+    fn trim_recent(&mut self, limit: usize) {
+        while self.recent_files.len() > limit {
+            self.recent_files.pop();
+        }
+    }
+
+    /// The `limit` most recently modified files, newest first.
+    fn recent(&self, limit: usize) -> Vec<&RecentFile> {
+        self.recent_files
+            .iter()
+            .map(|Reverse(file)| file)
+            .sorted_by(|a, b| b.cmp(a))
+            .take(limit)
+            .collect()
+    }
+
+    /// Number of files tallied, whether or not they were sniffed for a mimetype.
+    fn file_count(&self) -> usize {
+        // Without an extension tally (`--mimetypes-only`), every file has a mimetype instead.
+        let counts = if self.extensions.is_empty() {
             &self.mimetypes
         } else {
             &self.extensions
         };
-        match format {
-            OutputFormat::Text => self.display_text(data),
-            OutputFormat::Csv => self.display_csv(data, use_mime),
-            OutputFormat::Json => self.display_json(data, use_mime),
+        counts.values().map(|&count| count as usize).sum()
+    }
+
+    /// Number of errors hit, including those beyond `--max-errors` that weren't kept.
+    fn error_count(&self) -> usize {
+        self.errors.len() + self.suppressed_errors
+    }
+
+    /// Whether the scan found neither files nor folders.
+    fn is_empty(&self) -> bool {
+        self.file_count() == 0 && self.folders.is_empty()
+    }
+
+    /// Folds another report into this one, e.g. when several roots are scanned together.
+    fn merge(&mut self, other: Report, max_errors: Option<usize>) {
+        for (key, count) in other.extensions {
+            *self.extensions.entry(key).or_default() += count;
+        }
+        for (key, count) in other.mimetypes {
+            *self.mimetypes.entry(key).or_default() += count;
+        }
+        for (key, size) in other.mimetype_sizes {
+            *self.mimetype_sizes.entry(key).or_default() += size;
+        }
+        for (path, count) in other.dir_file_counts {
+            *self.dir_file_counts.entry(path).or_default() += count;
+        }
+        for (path, sizes) in other.top_level_dirs {
+            let merged = self.top_level_dirs.entry(path).or_default();
+            merged.shallow_size += sizes.shallow_size;
+            merged.recursive_size += sizes.recursive_size;
+            merged.files += sizes.files;
+        }
+        self.folders.extend(other.folders);
+        self.file_records.extend(other.file_records);
+        // Each side is already bounded; `recent` trims the union when it is displayed.
+        self.recent_files.extend(other.recent_files);
+        self.slow_files.extend(other.slow_files);
+        self.size += other.size;
+        for error in other.errors {
+            self.push_error(error, max_errors);
+        }
+        self.suppressed_errors += other.suppressed_errors;
+        self.uppercase_ext_files += other.uppercase_ext_files;
+        self.mixedcase_ext_files += other.mixedcase_ext_files;
+        self.truncated |= other.truncated;
+        self.excluded_symlinks += other.excluded_symlinks;
+        self.excluded_special_files += other.excluded_special_files;
+        self.collapsed_link_paths += other.collapsed_link_paths;
+        self.cold_files += other.cold_files;
+        self.cold_size += other.cold_size;
+        for (uid, size) in other.owner_sizes {
+            *self.owner_sizes.entry(uid).or_default() += size;
+        }
+        self.case_collisions.extend(other.case_collisions);
+        self.sniffed_files += other.sniffed_files;
+        self.bytes_read += other.bytes_read;
+        for (path, count) in other.dir_entry_counts {
+            *self.dir_entry_counts.entry(path).or_default() += count;
+        }
+        self.suspect_files.extend(other.suspect_files);
+        self.misnamed_files.extend(other.misnamed_files);
+        if self.size_histogram.len() < other.size_histogram.len() {
+            self.size_histogram.resize(other.size_histogram.len(), 0);
+        }
+        for (merged, count) in self.size_histogram.iter_mut().zip(other.size_histogram) {
+            *merged += count;
+        }
+        for (ext, counts) in other.ext_depths {
+            let merged = self.ext_depths.entry(ext).or_default();
+            if merged.len() < counts.len() {
+                merged.resize(counts.len(), 0);
+            }
+            for (merged, count) in merged.iter_mut().zip(counts) {
+                *merged += count;
+            }
+        }
+        for (name, (count, size)) in other.file_names {
+            let merged = self.file_names.entry(name).or_default();
+            merged.0 += count;
+            merged.1 += size;
+        }
+        for (path, extensions) in other.rollup {
+            let merged = self.rollup.entry(path).or_default();
+            for (key, count) in extensions {
+                *merged.entry(key).or_default() += count;
+            }
         }
     }
 
-    fn display_text(&self, data: &BTreeMap<String, i32>) {
-        let num_files: i32 = data.values().sum();
-        let size = HumanBytes(self.size);
-        let error_info = if self.errors.is_empty() {
-            String::new()
-        } else {
-            format!(", {} errors", self.errors.len())
-        };
-        println!(
-            "{num_files} files, {} folders, {size}{error_info}",
-            self.folders.len()
-        );
-        for (key, count) in data.iter().sorted_by(|a, b| b.1.cmp(a.1)) {
-            println!("{key}: {count}");
+    /// The `limit` directories with the most immediate child files, most crowded first.
+    fn fat_dirs(&self, limit: usize) -> Vec<(&PathBuf, usize)> {
+        self.dir_file_counts
+            .iter()
+            .map(|(path, count)| (path, *count))
+            .sorted_by(|a, b| b.1.cmp(&a.1))
+            .take(limit)
+            .collect()
+    }
+
+    /// The `limit` directories with the most entries in their subtree, most crowded first.
+    fn largest_dirs(&self, limit: usize) -> Vec<(&PathBuf, usize)> {
+        self.dir_entry_counts
+            .iter()
+            .map(|(path, count)| (path, *count))
+            .sorted_by(|a, b| b.1.cmp(&a.1))
+            .take(limit)
+            .collect()
+    }
+
+    /// The `limit` most common file names with their count and total size, most common
+    /// first.
+    fn top_names(&self, limit: usize) -> Vec<(&str, i32, u64)> {
+        self.file_names
+            .iter()
+            .map(|(name, &(count, size))| (name.as_str(), count, size))
+            .sorted_by(|a, b| b.1.cmp(&a.1))
+            .take(limit)
+            .collect()
+    }
+
+    /// The `limit` extensions with the most files, with their file counts by depth, most
+    /// common first.
+    fn depth_spread(&self, limit: usize) -> Vec<(&str, &[usize])> {
+        self.ext_depths
+            .iter()
+            .map(|(ext, counts)| (ext.as_str(), counts.as_slice()))
+            .sorted_by_key(|(_, counts)| Reverse(counts.iter().sum::<usize>()))
+            .take(limit)
+            .collect()
+    }
+
+    /// Counts an entry at `depth` towards each directory above it, up to but not including
+    /// the scan target.
+    fn count_dir_entry(&mut self, path: &Path, depth: usize) {
+        for dir in path.ancestors().skip(1).take(depth.saturating_sub(1)) {
+            match self.dir_entry_counts.get_mut(dir) {
+                Some(count) => *count += 1,
+                None => {
+                    self.dir_entry_counts.insert(dir.to_path_buf(), 1);
+                }
+            }
+        }
+    }
+
+    /// Top-level directories, largest subtree first.
+    fn by_dir(&self) -> Vec<(&PathBuf, &DirSizes)> {
+        self.top_level_dirs
+            .iter()
+            .sorted_by(|a, b| b.1.recursive_size.cmp(&a.1.recursive_size))
+            .collect()
+    }
+
+    /// `size` as a percentage of the whole report's size; 0 if the report has no size at all.
+    fn share_of_size(&self, size: u64) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        size as f64 * 100.0 / self.size as f64
+    }
+
+    /// Extensions found in the scan that resolve to the same mimetype, by mimetype. Only
+    /// groups with more than one spelling are returned.
+    fn extension_aliases(&self) -> BTreeMap<&'static str, Vec<&str>> {
+        let mut aliases: BTreeMap<&'static str, Vec<&str>> = BTreeMap::new();
+        for ext in self.extensions.keys().filter(|ext| !ext.is_empty()) {
+            if let Some(mimetype) = mime_guess::from_ext(ext).first_raw() {
+                aliases.entry(mimetype).or_default().push(ext);
+            }
+        }
+        aliases.retain(|_, exts| exts.len() > 1);
+        aliases
+    }
+
+    /// Owners by the bytes they hold, largest first.
+    fn by_owner(&self) -> Vec<(u32, u64)> {
+        self.owner_sizes
+            .iter()
+            .map(|(&uid, &size)| (uid, size))
+            .sorted_by(|a, b| b.1.cmp(&a.1))
+            .collect()
+    }
+
+    fn display(&self, out: &mut dyn Write, options: &DisplayOptions) -> io::Result<()> {
+        if options.print0_errors {
+            return self.display_errors_print0(out, options);
+        }
+        if options.oneline {
+            return self.display_oneline(out);
+        }
+        let use_mime = options.use_mime;
+        let rows = if use_mime {
+            self.mimetype_rows(options)
+        } else {
+            rows(&self.extensions, None, options)
+        };
+        match options.format {
+            OutputFormat::Text => {
+                self.display_text(out, &rows, options)?;
+                if let Some(limit) = options.fat_dirs {
+                    self.display_fat_dirs_text(out, limit, options)?;
+                }
+                if let Some(limit) = options.largest_dirs {
+                    self.display_largest_dirs_text(out, limit, options)?;
+                }
+                if let Some(limit) = options.top_names {
+                    self.display_top_names_text(out, limit, options)?;
+                }
+                if let Some(limit) = options.sparkline {
+                    self.display_sparkline_text(out, limit, options)?;
+                }
+                if options.by_dir {
+                    self.display_by_dir_text(out, options)?;
+                }
+                if let Some(scheme) = options.histogram {
+                    self.display_histogram_text(out, scheme, options)?;
+                }
+                if let Some(depth) = options.rollup_depth {
+                    self.display_rollup_text(out, depth, options)?;
+                }
+                if options.by_owner {
+                    self.display_by_owner_text(out, options)?;
+                }
+                if let Some(limit) = options.recent {
+                    self.display_recent_text(out, limit, options)?;
+                }
+                if options.slow_files {
+                    self.display_slow_files_text(out, options)?;
+                }
+                if options.suspect_files {
+                    self.display_suspect_files_text(out, options)?;
+                }
+                if options.case_collisions {
+                    self.display_case_collisions_text(out, options)?;
+                }
+                if options.list_errors {
+                    self.display_errors_text(out, options)?;
+                }
+                Ok(())
+            }
+            OutputFormat::Csv => self.display_csv(out, &rows, options),
+            OutputFormat::Json if options.flatten => self.display_json_flat(out, options),
+            OutputFormat::Json => self.display_json(out, &rows, options),
+            OutputFormat::Prometheus => self.display_prometheus(out, &rows, use_mime),
+            OutputFormat::Html => self.display_html(out, &rows, options),
+        }
+    }
+
+    /// The headline of a report, e.g. `27 files, 5 folders, 1.32 KiB, 2 errors`.
+    fn summary(&self, options: &DisplayOptions) -> String {
+        let size = friendly_bytes(self.size, options.precision);
+        let error_info = if self.error_count() == 0 {
+            String::new()
+        } else {
+            format!(", {}", counted(self.error_count(), "error"))
+        };
+        let truncated_info = if self.truncated { " (truncated)" } else { "" };
+        format!(
+            "{}, {}, {size}{error_info}{truncated_info}",
+            counted(self.file_count(), "file"),
+            counted(self.folders.len(), "folder")
+        )
+    }
+
+    fn display_text(
+        &self,
+        out: &mut dyn Write,
+        rows: &[Row],
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        if !options.no_summary {
+            self.display_summary_text(out, options)?;
+        }
+        if options.both {
+            writeln!(out, "Extensions:")?;
+            display_rows_text(out, rows, options)?;
+            writeln!(out)?;
+            writeln!(out, "Mimetypes:")?;
+            return display_rows_text(out, &self.mimetype_rows(options), options);
+        }
+        display_rows_text(out, rows, options)
+    }
+
+    /// The lines above the rows of the text report: the headline plus notes on extension
+    /// case, what was left out of the size total and how much was read for sniffing.
+    fn display_summary_text(
+        &self,
+        out: &mut dyn Write,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        if !options.targets.is_empty() {
+            let targets = options
+                .targets
+                .iter()
+                .map(|target| target.display().to_string())
+                .join(", ");
+            writeln!(out, "Scan of {targets}:")?;
+        }
+        writeln!(out, "{}", self.summary(options))?;
+        if self.uppercase_ext_files + self.mixedcase_ext_files > 0 {
+            writeln!(
+                out,
+                "{} with uppercase extensions, {} with mixed-case extensions",
+                self.uppercase_ext_files, self.mixedcase_ext_files
+            )?;
+        }
+        if self.excluded_symlinks + self.excluded_special_files > 0 {
+            writeln!(
+                out,
+                "excluded {} symlinks / {} special files from size total",
+                self.excluded_symlinks, self.excluded_special_files
+            )?;
+        }
+        if self.collapsed_link_paths > 0 {
+            writeln!(
+                out,
+                "skipped {} leading to files already counted under another path",
+                counted(self.collapsed_link_paths, "path")
+            )?;
+        }
+        if self.sniffed_files > 0 {
+            writeln!(
+                out,
+                "read {} to sniff {}",
+                friendly_bytes(self.bytes_read, options.precision),
+                counted(self.sniffed_files, "file")
+            )?;
+        }
+        if options.cold_data {
+            writeln!(
+                out,
+                "cold data: {} across {}, {:.*}% of total",
+                friendly_bytes(self.cold_size, options.precision),
+                counted(self.cold_files, "file"),
+                options.precision,
+                self.share_of_size(self.cold_size)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The mimetype rows with their sizes. Sizes are only tracked per mimetype, since that
+    /// is where they are most telling.
+    fn mimetype_rows(&self, options: &DisplayOptions) -> Vec<Row> {
+        rows(&self.mimetypes, Some(&self.mimetype_sizes), options)
+    }
+
+    /// Writes e.g. `27f 5d 1KiB`, the file and folder counts and a whole-unit size.
+    fn display_oneline(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{}f {}d {}",
+            self.file_count(),
+            self.folders.len(),
+            friendly_bytes(self.size, 0).replace(' ', "")
+        )
+    }
+
+    fn display_csv(
+        &self,
+        out: &mut dyn Write,
+        rows: &[Row],
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        for target in &options.targets {
+            writeln!(out, "# target: {}", target.display())?;
+        }
+        if options.both {
+            display_csv_rows(out, rows, false, options)?;
+            writeln!(out)?;
+            return display_csv_rows(out, &self.mimetype_rows(options), true, options);
+        }
+        display_csv_rows(out, rows, options.use_mime, options)
+    }
+
+    fn display_json(
+        &self,
+        out: &mut dyn Write,
+        rows: &[Row],
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out, "{{")?;
+        if !options.targets.is_empty() {
+            let targets = options
+                .targets
+                .iter()
+                .map(|target| format!("\"{}\"", json_escape(&target.to_string_lossy())))
+                .join(", ");
+            writeln!(out, "  \"targets\": [{targets}],")?;
+        }
+        if let Some(scan_options) = &options.scan_options {
+            let option_entries: Vec<String> = scan_options
+                .effective()
+                .into_iter()
+                .map(|(name, value)| format!("    \"{name}\": {value}"))
+                .collect();
+            writeln!(out, "  \"options\": {{")?;
+            writeln!(out, "{}", option_entries.join(",\n"))?;
+            writeln!(out, "  }},")?;
+        }
+        writeln!(out, "  \"files\": {},", self.file_count())?;
+        writeln!(out, "  \"folders\": {},", self.folders.len())?;
+        writeln!(out, "  \"size\": {},", self.size)?;
+        writeln!(out, "  \"truncated\": {},", self.truncated)?;
+        writeln!(
+            out,
+            "  \"uppercase_ext_files\": {},",
+            self.uppercase_ext_files
+        )?;
+        writeln!(
+            out,
+            "  \"mixedcase_ext_files\": {},",
+            self.mixedcase_ext_files
+        )?;
+        writeln!(out, "  \"excluded_symlinks\": {},", self.excluded_symlinks)?;
+        writeln!(
+            out,
+            "  \"excluded_special_files\": {},",
+            self.excluded_special_files
+        )?;
+        writeln!(
+            out,
+            "  \"collapsed_link_paths\": {},",
+            self.collapsed_link_paths
+        )?;
+        writeln!(out, "  \"sniffed_files\": {},", self.sniffed_files)?;
+        writeln!(out, "  \"bytes_read\": {},", self.bytes_read)?;
+        if options.cold_data {
+            writeln!(
+                out,
+                "  \"cold_data\": {{\"files\": {}, \"size\": {}, \"pct\": {:.*}}},",
+                self.cold_files,
+                self.cold_size,
+                options.precision,
+                self.share_of_size(self.cold_size)
+            )?;
+        }
+        if options.both {
+            display_json_groups(out, rows, false, options)?;
+            display_json_groups(out, &self.mimetype_rows(options), true, options)?;
+        } else {
+            display_json_groups(out, rows, options.use_mime, options)?;
+        }
+        if let Some(limit) = options.fat_dirs {
+            let fat_dir_entries: Vec<String> = self
+                .fat_dirs(limit)
+                .into_iter()
+                .map(|(path, count)| {
+                    format!(
+                        "    {{\n      \"path\": \"{}\",\n      \"file_count\": {count}\n    }}",
+                        json_escape(&options.path(path).to_string_lossy())
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"fat_dirs\": [")?;
+            writeln!(out, "{}", fat_dir_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if let Some(limit) = options.largest_dirs {
+            let largest_dir_entries: Vec<String> = self
+                .largest_dirs(limit)
+                .into_iter()
+                .map(|(path, count)| {
+                    format!(
+                        "    {{\n      \"path\": \"{}\",\n      \"entry_count\": {count}\n    }}",
+                        json_escape(&options.path(path).to_string_lossy())
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"largest_dirs\": [")?;
+            writeln!(out, "{}", largest_dir_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if let Some(limit) = options.top_names {
+            let name_entries: Vec<String> = self
+                .top_names(limit)
+                .into_iter()
+                .map(|(name, count, size)| {
+                    format!(
+                        "    {{\n      \"name\": \"{}\",\n      \"count\": {count},\n      \"size\": {size}\n    }}",
+                        json_escape(name)
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"top_names\": [")?;
+            writeln!(out, "{}", name_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if let Some(limit) = options.sparkline {
+            let depth_entries: Vec<String> = self
+                .depth_spread(limit)
+                .into_iter()
+                .map(|(ext, counts)| {
+                    format!(
+                        "    {{\n      \"extension\": \"{}\",\n      \"by_depth\": [{}]\n    }}",
+                        json_escape(ext),
+                        counts.iter().join(", ")
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"extensions_by_depth\": [")?;
+            writeln!(out, "{}", depth_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if options.by_dir {
+            let by_dir_entries: Vec<String> = self
+                .by_dir()
+                .into_iter()
+                .map(|(path, sizes)| {
+                    format!(
+                        "    {{\n      \"path\": \"{}\",\n      \"shallow_size\": {},\n      \"recursive_size\": {},\n      \"pct\": {:.*},\n      \"files\": {}\n    }}",
+                        json_escape(&options.path(path).to_string_lossy()),
+                        sizes.shallow_size,
+                        sizes.recursive_size,
+                        options.precision,
+                        self.share_of_size(sizes.recursive_size),
+                        sizes.files
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"by_dir\": [")?;
+            writeln!(out, "{}", by_dir_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if let Some(scheme) = options.histogram {
+            let bucket_entries: Vec<String> = self
+                .histogram(scheme)
+                .into_iter()
+                .map(|((lower, upper), count)| {
+                    let upper = upper.map_or("null".to_string(), |upper| upper.to_string());
+                    format!("      {{ \"min\": {lower}, \"max\": {upper}, \"count\": {count} }}")
+                })
+                .collect();
+            writeln!(out, "  \"size_histogram\": {{")?;
+            writeln!(out, "    \"base\": {},", scheme.base)?;
+            writeln!(out, "    \"buckets\": [")?;
+            writeln!(out, "{}", bucket_entries.join(",\n"))?;
+            writeln!(out, "    ]")?;
+            writeln!(out, "  }},")?;
+        }
+        if options.rollup_depth.is_some() {
+            let rollup_entries: Vec<String> = self
+                .rollup
+                .iter()
+                .map(|(path, extensions)| {
+                    let extensions = extensions
+                        .iter()
+                        .sorted_by(|a, b| b.1.cmp(a.1))
+                        .map(|(key, count)| format!("\"{}\": {count}", json_escape(key)))
+                        .join(", ");
+                    format!(
+                        "    \"{}\": {{{extensions}}}",
+                        json_escape(&options.path(path).to_string_lossy())
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"rollup\": {{")?;
+            writeln!(out, "{}", rollup_entries.join(",\n"))?;
+            writeln!(out, "  }},")?;
+        }
+        if options.by_owner {
+            let names = owner::user_names();
+            let by_owner_entries: Vec<String> = self
+                .by_owner()
+                .into_iter()
+                .map(|(uid, size)| {
+                    format!(
+                        "    {{\n      \"owner\": \"{}\",\n      \"uid\": {uid},\n      \"size\": {size}\n    }}",
+                        json_escape(&owner::display_name(&names, uid))
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"by_owner\": [")?;
+            writeln!(out, "{}", by_owner_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if let Some(limit) = options.recent {
+            let recent_entries: Vec<String> = self
+                .recent(limit)
+                .into_iter()
+                .map(|file| {
+                    format!(
+                        "    {{\n      \"path\": \"{}\",\n      \"modified\": {},\n      \"size\": {}\n    }}",
+                        json_escape(&options.path(&file.path).to_string_lossy()),
+                        options.json_time(file.modified),
+                        file.size
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"recent\": [")?;
+            writeln!(out, "{}", recent_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if options.include_files {
+            let file_entries = self.file_records.iter().map(|file| {
+                    let mime = match &file.mime {
+                        Some(mime) => format!("\"{}\"", json_escape(mime)),
+                        None => "null".to_string(),
+                    };
+                    format!(
+                        "    {{\n      \"path\": \"{}\",\n      \"ext\": \"{}\",\n      \"mime\": {mime},\n      \"size\": {}\n    }}",
+                        json_escape(&options.path(&file.path).to_string_lossy()),
+                        json_escape(&file.ext),
+                        file.size
+                    )
+                });
+            writeln!(out, "  \"file_list\": [")?;
+            write_json_entries(out, file_entries)?;
+            writeln!(out, "  ],")?;
+        }
+        if options.slow_files {
+            let slow_entries: Vec<String> = self
+                .slow_files
+                .iter()
+                .map(|file| {
+                    format!(
+                        "    {{\n      \"path\": \"{}\",\n      \"millis\": {}\n    }}",
+                        json_escape(&options.path(&file.path).to_string_lossy()),
+                        file.elapsed.as_millis()
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"slow_files\": [")?;
+            writeln!(out, "{}", slow_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if options.suspect_files {
+            let suspect_entries: Vec<String> = self
+                .suspect_files
+                .iter()
+                .map(|path| {
+                    format!(
+                        "    \"{}\"",
+                        json_escape(&options.path(path).to_string_lossy())
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"suspect_files\": [")?;
+            writeln!(out, "{}", suspect_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        if options.case_collisions {
+            let collision_entries: Vec<String> = self
+                .case_collisions
+                .iter()
+                .map(|paths| {
+                    let paths = paths
+                        .iter()
+                        .map(|path| {
+                            format!("\"{}\"", json_escape(&options.path(path).to_string_lossy()))
+                        })
+                        .join(", ");
+                    format!("    [{paths}]")
+                })
+                .collect();
+            writeln!(out, "  \"case_collisions\": [")?;
+            writeln!(out, "{}", collision_entries.join(",\n"))?;
+            writeln!(out, "  ],")?;
+        }
+        // Errors can be uncapped, so each entry is written as soon as it is formatted.
+        let error_entries = self.errors.iter().map(|e| {
+            format!(
+                "    {{\n      \"path\": \"{}\",\n      \"message\": \"{}\",\n      \"kind\": \"{}\"\n    }}",
+                json_escape(&options.path(&e.path).to_string_lossy()),
+                json_escape(&e.message),
+                e.kind.name()
+            )
+        });
+        writeln!(out, "  \"errors\": [")?;
+        write_json_entries(out, error_entries)?;
+        writeln!(out, "  ],")?;
+        writeln!(out, "  \"suppressed_errors\": {}", self.suppressed_errors)?;
+        writeln!(out, "}}")
+    }
+
+    fn display_fat_dirs_text(
+        &self,
+        out: &mut dyn Write,
+        limit: usize,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "Directories with the most files:")?;
+        for (path, count) in self.fat_dirs(limit) {
+            writeln!(out, "{}: {count}", options.path(path).display())?;
+        }
+        Ok(())
+    }
+
+    fn display_suspect_files_text(
+        &self,
+        out: &mut dyn Write,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        if self.suspect_files.is_empty() {
+            return Ok(());
+        }
+        writeln!(out)?;
+        writeln!(out, "Possibly truncated files:")?;
+        for path in &self.suspect_files {
+            writeln!(out, "{}", options.path(path).display())?;
+        }
+        Ok(())
+    }
+
+    fn display_largest_dirs_text(
+        &self,
+        out: &mut dyn Write,
+        limit: usize,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "Directories with the most entries:")?;
+        for (path, count) in self.largest_dirs(limit) {
+            writeln!(out, "{}: {count}", options.path(path).display())?;
+        }
+        Ok(())
+    }
+
+    fn display_top_names_text(
+        &self,
+        out: &mut dyn Write,
+        limit: usize,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "Most common file names:")?;
+        for (name, count, size) in self.top_names(limit) {
+            writeln!(
+                out,
+                "{name}: {count} ({})",
+                friendly_bytes(size, options.precision)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn display_sparkline_text(
+        &self,
+        out: &mut dyn Write,
+        limit: usize,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        let spread = self.depth_spread(limit);
+        let key_width = spread
+            .iter()
+            .map(|(ext, _)| ext.chars().count())
+            .max()
+            .unwrap_or(0);
+        // Every line spans the same depths, so the columns line up across extensions.
+        let depths = spread
+            .iter()
+            .map(|(_, counts)| counts.len())
+            .max()
+            .unwrap_or(0);
+        writeln!(out)?;
+        writeln!(out, "Extensions by depth, from the top level down:")?;
+        for (ext, counts) in spread {
+            if options.plain_sparklines {
+                let mut padded = (0..depths).map(|depth| counts.get(depth).copied().unwrap_or(0));
+                writeln!(out, "{ext:<key_width$}  {}", padded.join(" "))?;
+            } else {
+                writeln!(
+                    out,
+                    "{ext:<key_width$}  {:<depths$}  {}",
+                    sparkline(counts),
+                    counts.iter().sum::<usize>()
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn display_recent_text(
+        &self,
+        out: &mut dyn Write,
+        limit: usize,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "Most recently modified:")?;
+        for file in self.recent(limit) {
+            writeln!(
+                out,
+                "{}: {} ({})",
+                options.path(&file.path).display(),
+                options.time(file.modified),
+                friendly_bytes(file.size, options.precision)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn display_by_dir_text(&self, out: &mut dyn Write, options: &DisplayOptions) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "By top-level directory:")?;
+        for (path, sizes) in self.by_dir() {
+            writeln!(
+                out,
+                "{}: {} shallow, {} recursive ({:.*}%), {} files",
+                options.path(path).display(),
+                friendly_bytes(sizes.shallow_size, options.precision),
+                friendly_bytes(sizes.recursive_size, options.precision),
+                options.precision,
+                self.share_of_size(sizes.recursive_size),
+                sizes.files
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The `--size-histogram` buckets along with how many files are in each; a fixed number
+    /// of buckets is always shown in full.
+    fn histogram(&self, scheme: HistogramScheme) -> Vec<((u64, Option<u64>), usize)> {
+        let buckets = scheme.buckets.unwrap_or(self.size_histogram.len());
+        (0..buckets)
+            .map(|index| {
+                let count = self.size_histogram.get(index).copied().unwrap_or_default();
+                (scheme.bounds(index), count)
+            })
+            .collect()
+    }
+
+    fn display_histogram_text(
+        &self,
+        out: &mut dyn Write,
+        scheme: HistogramScheme,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "File sizes, in buckets of powers of {}:", scheme.base)?;
+        for ((lower, upper), count) in self.histogram(scheme) {
+            let lower = friendly_bytes(lower, options.precision);
+            match upper {
+                Some(upper) => writeln!(
+                    out,
+                    "{lower} to {}: {count}",
+                    friendly_bytes(upper, options.precision)
+                )?,
+                None => writeln!(out, "{lower} and up: {count}")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn display_rollup_text(
+        &self,
+        out: &mut dyn Write,
+        depth: usize,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "Extensions by directory, down to depth {depth}:")?;
+        for (path, extensions) in &self.rollup {
+            writeln!(out, "{}:", options.path(path).display())?;
+            for (key, count) in extensions.iter().sorted_by(|a, b| b.1.cmp(a.1)) {
+                writeln!(out, "  {key}: {count}")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn display_by_owner_text(
+        &self,
+        out: &mut dyn Write,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        let names = owner::user_names();
+        writeln!(out)?;
+        writeln!(out, "By owner:")?;
+        for (uid, size) in self.by_owner() {
+            writeln!(
+                out,
+                "{}: {}",
+                owner::display_name(&names, uid),
+                friendly_bytes(size, options.precision)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn display_slow_files_text(
+        &self,
+        out: &mut dyn Write,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        if self.slow_files.is_empty() {
+            return Ok(());
+        }
+        writeln!(out)?;
+        writeln!(out, "Slow files:")?;
+        for file in &self.slow_files {
+            writeln!(
+                out,
+                "{}: {} ms",
+                options.path(&file.path).display(),
+                file.elapsed.as_millis()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn display_case_collisions_text(
+        &self,
+        out: &mut dyn Write,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "Case collisions:")?;
+        for paths in &self.case_collisions {
+            let paths = paths
+                .iter()
+                .map(|path| options.path(path).display().to_string())
+                .join(", ");
+            writeln!(out, "{paths}")?;
+        }
+        Ok(())
+    }
+
+    fn display_errors_text(&self, out: &mut dyn Write, options: &DisplayOptions) -> io::Result<()> {
+        if self.error_count() == 0 {
+            return Ok(());
+        }
+        writeln!(out)?;
+        if options.dedupe_errors {
+            return self.display_deduped_errors_text(out, options);
+        }
+        writeln!(out, "Errors:")?;
+        for error in &self.errors {
+            match error.kind.label() {
+                Some(label) => writeln!(
+                    out,
+                    "{}: [{label}] {}",
+                    options.path(&error.path).display(),
+                    error.message
+                )?,
+                None => writeln!(
+                    out,
+                    "{}: {}",
+                    options.path(&error.path).display(),
+                    error.message
+                )?,
+            }
+        }
+        if self.suppressed_errors > 0 {
+            writeln!(out, "... and {} more", self.suppressed_errors)?;
+        }
+        Ok(())
+    }
+
+    /// Lists every distinct error message once, most frequent first, followed by up to
+    /// [`ERROR_EXAMPLES`] of the paths that hit it. The failing path is taken out of each
+    /// message first, or no two messages would ever be the same.
+    fn display_deduped_errors_text(
+        &self,
+        out: &mut dyn Write,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        let messages: Vec<String> = self
+            .errors
+            .iter()
+            .map(|error| {
+                let message = error
+                    .message
+                    .replace(&format!("{:?}", error.path), "<path>")
+                    // As `walkdir` puts it: "IO error for operation on /some/dir: ..."
+                    .replace(&format!(" {}:", error.path.display()), " <path>:");
+                match error.kind.label() {
+                    Some(label) => format!("[{label}] {message}"),
+                    None => message,
+                }
+            })
+            .collect();
+        // Grouped in order of first appearance, so equally frequent messages keep it.
+        let mut by_message: Vec<(&str, Vec<&Path>)> = Vec::new();
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for (error, message) in self.errors.iter().zip(&messages) {
+            match positions.get(message.as_str()) {
+                Some(&position) => by_message[position].1.push(&error.path),
+                None => {
+                    positions.insert(message, by_message.len());
+                    by_message.push((message, vec![&error.path]));
+                }
+            }
+        }
+        by_message.sort_by_key(|(_, paths)| Reverse(paths.len()));
+
+        writeln!(
+            out,
+            "Errors: {} among {}",
+            counted(by_message.len(), "unique message"),
+            counted(self.error_count(), "error")
+        )?;
+        for (message, paths) in &by_message {
+            writeln!(out, "{message} ({})", counted(paths.len(), "path"))?;
+            for path in paths.iter().take(ERROR_EXAMPLES) {
+                writeln!(out, "  {}", options.path(path).display())?;
+            }
+            if paths.len() > ERROR_EXAMPLES {
+                writeln!(out, "  ... and {} more", paths.len() - ERROR_EXAMPLES)?;
+            }
+        }
+        if self.suppressed_errors > 0 {
+            writeln!(out, "... and {} more", self.suppressed_errors)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the raw bytes of each failing path followed by a NUL, so paths containing
+    /// newlines or invalid UTF-8 survive the trip through `xargs -0`.
+    fn display_errors_print0(
+        &self,
+        out: &mut dyn Write,
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        for error in &self.errors {
+            out.write_all(options.path(&error.path).as_os_str().as_encoded_bytes())?;
+            out.write_all(b"\0")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the report as one flat JSON object, e.g.
+    /// `{"files":27,"size":12345,"ext.png":2,"mime.image/png":1}`.
+    ///
+    /// Group keys are prefixed with `ext.` or `mime.`. Inside the key, `\` is written as `\\`
+    /// and `.` as `\.`, so the extension `tar.gz` becomes `ext.tar\.gz` and can't collide
+    /// with anything else; the result is then JSON-escaped as usual.
+    fn display_json_flat(&self, out: &mut dyn Write, options: &DisplayOptions) -> io::Result<()> {
+        let mut fields = vec![
+            format!("\"files\":{}", self.file_count()),
+            format!("\"folders\":{}", self.folders.len()),
+            format!("\"size\":{}", self.size),
+            format!("\"errors\":{}", self.error_count()),
+            format!("\"truncated\":{}", self.truncated),
+        ];
+        let extension_rows = rows(&self.extensions, None, options);
+        let mimetype_rows = rows(&self.mimetypes, Some(&self.mimetype_sizes), options);
+        for (prefix, rows) in [("ext", &extension_rows), ("mime", &mimetype_rows)] {
+            fields.extend(rows.iter().map(|row| {
+                let flat_key = format!("{prefix}.{}", flat_key_escape(&row.key));
+                format!("\"{}\":{}", json_escape(&flat_key), row.count)
+            }));
+        }
+        fields.extend(mimetype_rows.iter().map(|row| {
+            let flat_key = format!("mime_size.{}", flat_key_escape(&row.key));
+            format!(
+                "\"{}\":{}",
+                json_escape(&flat_key),
+                row.size.unwrap_or_default()
+            )
+        }));
+        writeln!(out, "{{{}}}", fields.join(","))
+    }
+
+    /// Writes the report as a standalone HTML page: the summary, a table of groups with a bar
+    /// per row that sorts when a column header is clicked, and the recorded errors. Styles and
+    /// script are inline so the file can be mailed around as is.
+    fn display_html(
+        &self,
+        out: &mut dyn Write,
+        rows: &[Row],
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html lang=\"en\">")?;
+        writeln!(out, "<head>")?;
+        writeln!(out, "<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>sumdir report</title>")?;
+        writeln!(out, "<style>{HTML_STYLE}</style>")?;
+        writeln!(out, "</head>")?;
+        writeln!(out, "<body>")?;
+        writeln!(out, "<h1>sumdir report</h1>")?;
+        writeln!(out, "<p>{}</p>", html_escape(&self.summary(options)))?;
+        if self.is_empty() {
+            writeln!(out, "<p>No files or folders found.</p>")?;
+        } else {
+            self.display_html_table(out, rows, options)?;
+        }
+        if !self.errors.is_empty() {
+            writeln!(out, "<h2>Errors</h2>")?;
+            writeln!(out, "<ul>")?;
+            for error in &self.errors {
+                writeln!(
+                    out,
+                    "<li><code>{}</code>: {}</li>",
+                    html_escape(&options.path(&error.path).to_string_lossy()),
+                    html_escape(&error.message)
+                )?;
+            }
+            writeln!(out, "</ul>")?;
+        }
+        writeln!(out, "<script>{HTML_SORT_SCRIPT}</script>")?;
+        writeln!(out, "</body>")?;
+        writeln!(out, "</html>")
+    }
+
+    /// The sortable table of groups in [`Report::display_html`].
+    fn display_html_table(
+        &self,
+        out: &mut dyn Write,
+        rows: &[Row],
+        options: &DisplayOptions,
+    ) -> io::Result<()> {
+        let key_name = if options.use_mime {
+            "Mimetype"
+        } else {
+            "Extension"
+        };
+        let max_count = rows.iter().map(|row| row.count).max().unwrap_or(1).max(1);
+        writeln!(out, "<table id=\"groups\">")?;
+        write!(
+            out,
+            "<thead><tr><th data-type=\"text\">{key_name}</th><th data-type=\"number\">Files</th>"
+        )?;
+        if options.use_mime {
+            write!(out, "<th data-type=\"number\">Size</th>")?;
+        }
+        writeln!(out, "<th data-type=\"number\"></th></tr></thead>")?;
+        writeln!(out, "<tbody>")?;
+        for row in rows {
+            write!(
+                out,
+                "<tr><td>{}</td><td data-value=\"{count}\">{count}</td>",
+                html_escape(&row.key),
+                count = row.count
+            )?;
+            if let Some(size) = row.size {
+                write!(
+                    out,
+                    "<td data-value=\"{size}\">{}</td>",
+                    html_escape(&friendly_bytes(size, options.precision))
+                )?;
+            }
+            let width = f64::from(row.count) / f64::from(max_count) * 100.0;
+            writeln!(
+                out,
+                "<td data-value=\"{}\"><div class=\"bar\" style=\"width: {width:.1}%\"></div></td></tr>",
+                row.count
+            )?;
+        }
+        writeln!(out, "</tbody>")?;
+        writeln!(out, "</table>")?;
+        Ok(())
+    }
+
+    /// Writes the report in the Prometheus text exposition format, suitable for the node
+    /// exporter's textfile collector.
+    fn display_prometheus(
+        &self,
+        out: &mut dyn Write,
+        rows: &[Row],
+        use_mime: bool,
+    ) -> io::Result<()> {
+        let totals = [
+            (
+                "sumdir_files_total",
+                "Number of files scanned.",
+                self.file_count() as u64,
+            ),
+            (
+                "sumdir_folders_total",
+                "Number of folders scanned.",
+                self.folders.len() as u64,
+            ),
+            (
+                "sumdir_bytes_total",
+                "Total size of the scanned files in bytes.",
+                self.size,
+            ),
+            (
+                "sumdir_errors_total",
+                "Number of errors hit while scanning.",
+                self.error_count() as u64,
+            ),
+            (
+                "sumdir_truncated",
+                "Whether the scan stopped early and only covers part of the target.",
+                u64::from(self.truncated),
+            ),
+        ];
+        for (name, help, value) in totals {
+            writeln!(out, "# HELP {name} {help}")?;
+            writeln!(out, "# TYPE {name} gauge")?;
+            writeln!(out, "{name} {value}")?;
+        }
+
+        let label = if use_mime { "mimetype" } else { "extension" };
+        writeln!(out, "# HELP sumdir_files Number of files per {label}.")?;
+        writeln!(out, "# TYPE sumdir_files gauge")?;
+        for row in rows {
+            writeln!(
+                out,
+                "sumdir_files{{{label}=\"{}\"}} {}",
+                prometheus_escape(&row.key),
+                row.count
+            )?;
+        }
+        if use_mime {
+            writeln!(
+                out,
+                "# HELP sumdir_bytes Size of the files per {label} in bytes."
+            )?;
+            writeln!(out, "# TYPE sumdir_bytes gauge")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "sumdir_bytes{{{label}=\"{}\"}} {}",
+                    prometheus_escape(&row.key),
+                    row.size.unwrap_or_default()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes a label value as required by the Prometheus text exposition format.
+fn prometheus_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Escapes a key segment for [`Report::display_json_flat`].
+fn flat_key_escape(key: &str) -> String {
+    key.replace('\\', "\\\\").replace('.', "\\.")
+}
+
+/// Escapes `value` for use inside a JSON string literal.
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if u32::from(c) < 0x20 => escaped.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes the text rows, one `key: count` line each or as bars with `--bars`.
+fn display_rows_text(
+    out: &mut dyn Write,
+    rows: &[Row],
+    options: &DisplayOptions,
+) -> io::Result<()> {
+    if let Some(line_width) = options.bars {
+        return display_bars_text(out, rows, line_width, options);
+    }
+    for row in rows {
+        match row.size {
+            Some(size) => writeln!(
+                out,
+                "{}: {} ({})",
+                row.key,
+                row.count,
+                friendly_bytes(size, options.precision)
+            )?,
+            None => writeln!(out, "{}: {}", row.key, row.count)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes one block of CSV rows under its column header, keyed by mimetype with `mime` and
+/// by extension otherwise.
+fn display_csv_rows(
+    out: &mut dyn Write,
+    rows: &[Row],
+    mime: bool,
+    options: &DisplayOptions,
+) -> io::Result<()> {
+    let header = if mime { "mimetype" } else { "extension" };
+    if let Some(columns) = &options.csv_columns {
+        return display_csv_columns(out, rows, header, columns, options);
+    }
+    if !options.no_csv_header {
+        let size_column = if mime { ",size" } else { "" };
+        writeln!(out, "{header},count{size_column}")?;
+    }
+    for row in rows {
+        match row.size {
+            Some(size) => writeln!(out, "{},{},{size}", row.key, row.count)?,
+            None => writeln!(out, "{},{}", row.key, row.count)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes the counts of `rows` as the `extensions` object of the JSON report, or with
+/// `mime` as the `mimetypes` object followed by `mimetype_sizes`.
+fn display_json_groups(
+    out: &mut dyn Write,
+    rows: &[Row],
+    mime: bool,
+    options: &DisplayOptions,
+) -> io::Result<()> {
+    let key_name = if mime { "mimetypes" } else { "extensions" };
+    let sorted_rows;
+    let rows = if options.sort_keys {
+        sorted_rows = rows_by_key(rows, options);
+        &sorted_rows
+    } else {
+        rows
+    };
+    let entries: Vec<String> = rows
+        .iter()
+        .map(|row| format!("    \"{}\": {}", json_escape(&row.key), row.count))
+        .collect();
+    writeln!(out, "  \"{key_name}\": {{")?;
+    writeln!(out, "{}", entries.join(",\n"))?;
+    writeln!(out, "  }},")?;
+    if mime {
+        let size_entries: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                format!(
+                    "    \"{}\": {}",
+                    json_escape(&row.key),
+                    row.size.unwrap_or_default()
+                )
+            })
+            .collect();
+        writeln!(out, "  \"mimetype_sizes\": {{")?;
+        writeln!(out, "{}", size_entries.join(",\n"))?;
+        writeln!(out, "  }},")?;
+    }
+    Ok(())
+}
+
+/// Writes CSV rows with exactly the value `columns` asked for, after the key column.
+fn display_csv_columns(
+    out: &mut dyn Write,
+    rows: &[Row],
+    header: &str,
+    columns: &[CsvColumn],
+    options: &DisplayOptions,
+) -> io::Result<()> {
+    let num_files: i32 = rows.iter().map(|row| row.count).sum();
+    if !options.no_csv_header {
+        write!(out, "{header}")?;
+        for column in columns {
+            let name = match column {
+                CsvColumn::Count => "count",
+                CsvColumn::Bytes => "bytes",
+                CsvColumn::Pct => "pct",
+            };
+            write!(out, ",{name}")?;
+        }
+        writeln!(out)?;
+    }
+    for row in rows {
+        write!(out, "{}", row.key)?;
+        for column in columns {
+            match column {
+                CsvColumn::Count => write!(out, ",{}", row.count)?,
+                CsvColumn::Bytes => write!(out, ",{}", row.size.unwrap_or_default())?,
+                CsvColumn::Pct => {
+                    let pct = f64::from(row.count) * 100.0 / f64::from(num_files.max(1));
+                    write!(out, ",{pct:.*}", options.precision)?;
+                }
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes text rows as aligned columns with a bar each, e.g. `png  ████      412`, scaled so
+/// the largest count fills the space `line_width` leaves for bars.
+fn display_bars_text(
+    out: &mut dyn Write,
+    rows: &[Row],
+    line_width: usize,
+    options: &DisplayOptions,
+) -> io::Result<()> {
+    let sizes: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            row.size
+                .map(|size| format!(" ({})", friendly_bytes(size, options.precision)))
+                .unwrap_or_default()
+        })
+        .collect();
+    let key_width = rows
+        .iter()
+        .map(|row| row.key.chars().count())
+        .max()
+        .unwrap_or(0);
+    let count_width = rows
+        .iter()
+        .map(|row| row.count.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let size_width = sizes.iter().map(String::len).max().unwrap_or(0);
+    let bar_width = line_width
+        .saturating_sub(key_width + count_width + size_width + 4)
+        .max(MIN_BAR_WIDTH);
+    let max_count = rows.iter().map(|row| row.count).max().unwrap_or(1).max(1);
+    for (row, size) in rows.iter().zip(&sizes) {
+        let share = f64::from(row.count) / f64::from(max_count);
+        // Round up so that no non-empty group ends up without a bar at all.
+        let length = (share * bar_width as f64).ceil() as usize;
+        writeln!(
+            out,
+            "{:<key_width$}  {:<bar_width$}  {:>count_width$}{size}",
+            row.key,
+            "█".repeat(length),
+            row.count
+        )?;
+    }
+    Ok(())
+}
+
+/// Draws `counts` as one glyph each, scaled to the largest; zeros are left blank so the
+/// depths a file type is missing from stand out.
+fn sparkline(counts: &[usize]) -> String {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+    counts
+        .iter()
+        .map(|&count| match count {
+            0 => ' ',
+            // Round up so that a single file still shows as the lowest bar.
+            _ => SPARK_GLYPHS[(count * SPARK_GLYPHS.len()).div_ceil(max) - 1],
+        })
+        .collect()
+}
+
+/// Whether the locale promises UTF-8 output, judged by the first of `LC_ALL`, `LC_CTYPE`
+/// and `LANG` that is set, like the C library does.
+fn locale_is_utf8() -> bool {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(std::env::var_os)
+        .find(|value| !value.is_empty());
+    match locale {
+        Some(locale) => {
+            let locale = locale.to_string_lossy().to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        }
+        // Windows consoles have no locale variables but render Unicode fine.
+        None => cfg!(windows),
+    }
+}
+
+/// Writes the already formatted entries of a JSON array one by one, separated by commas,
+/// so arrays with millions of entries never have to be held in memory as a whole.
+fn write_json_entries(
+    out: &mut dyn Write,
+    entries: impl Iterator<Item = String>,
+) -> io::Result<()> {
+    for (i, entry) in entries.enumerate() {
+        if i > 0 {
+            writeln!(out, ",")?;
+        }
+        write!(out, "{entry}")?;
+    }
+    writeln!(out)
+}
+
+/// Escapes text for use in HTML element content and double-quoted attribute values.
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Inline stylesheet of `--output html`.
+const HTML_STYLE: &str = "body { font-family: sans-serif; margin: 2em; } \
+table { border-collapse: collapse; } \
+th, td { padding: 0.2em 0.8em; text-align: left; border-bottom: 1px solid #ddd; } \
+th { cursor: pointer; user-select: none; } \
+td:last-child { width: 20em; } \
+.bar { height: 0.8em; background: #4a7fb5; }";
+
+/// Sorts the `--output html` table by the clicked column, toggling the direction on every
+/// click. Number columns sort by their `data-value`, text columns alphabetically.
+const HTML_SORT_SCRIPT: &str = r##"
+document.querySelectorAll("#groups th").forEach((th, column) => {
+  th.addEventListener("click", () => {
+    const body = th.closest("table").tBodies[0];
+    const numeric = th.dataset.type === "number";
+    const ascending = th.dataset.order !== "asc";
+    th.dataset.order = ascending ? "asc" : "desc";
+    const value = (row) => {
+      const cell = row.cells[column];
+      return numeric ? Number(cell.dataset.value) : cell.textContent;
+    };
+    Array.from(body.rows)
+      .sort((a, b) => {
+        const order = numeric ? value(a) - value(b) : value(a).localeCompare(value(b));
+        return ascending ? order : -order;
+      })
+      .forEach((row) => body.appendChild(row));
+  });
+});
+"##;
+
+/// One line of a tally as it is displayed.
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    key: String,
+    count: i32,
+    /// Total bytes of the group, for tallies that track sizes.
+    size: Option<u64>,
+}
+
+/// Rows to display for a tally, most frequent first.
+///
+/// Groups whose count dropped to zero (e.g. because every file in them was filtered out)
+/// are skipped so they never show up as empty rows. With `--top N`, everything past the
+/// first N rows is rolled up into a single row named after `--other-label`.
+fn rows(
+    data: &BTreeMap<String, i32>,
+    sizes: Option<&BTreeMap<String, u64>>,
+    options: &DisplayOptions,
+) -> Vec<Row> {
+    let mut rows: Vec<Row> = data
+        .iter()
+        .filter(|(_, count)| **count > 0)
+        .sorted_by(|a, b| b.1.cmp(a.1))
+        .map(|(key, count)| Row {
+            key: key.clone(),
+            count: *count,
+            size: sizes.map(|sizes| sizes.get(key).copied().unwrap_or_default()),
+        })
+        .collect();
+
+    if let Some(top) = options.top
+        && rows.len() > top
+    {
+        let rest = rows.split_off(top);
+        rows.push(Row {
+            key: options.other_label.clone(),
+            count: rest.iter().map(|row| row.count).sum(),
+            size: sizes.map(|_| rest.iter().filter_map(|row| row.size).sum()),
+        });
+    }
+    rows
+}
+
+/// `rows` in alphabetical key order. The bucket that `--top` folds the rest into stays last,
+/// as it isn't a key of its own.
+fn rows_by_key(rows: &[Row], options: &DisplayOptions) -> Vec<Row> {
+    let named = options.top.map_or(rows.len(), |top| top.min(rows.len()));
+    let (named, other) = rows.split_at(named);
+    named
+        .iter()
+        .sorted_by(|a, b| a.key.cmp(&b.key))
+        .chain(other)
+        .cloned()
+        .collect()
+}
+
+/// Number of bytes looked at when sniffing a file's content, unless `--sniff-bytes` says
+/// otherwise.
+const SNIFF_WINDOW: usize = 8192;
+
+/// The mimetype of `path`'s content, along with how many bytes were read to find it.
+///
+/// The fallback mimetype is only reported once the largest window allowed has been looked
+/// at, or the whole file if that is smaller.
+fn detect_mimetype(path: &Path, options: &ScanOptions) -> Result<(String, usize)> {
+    let fallback = if options.no_mime_fallback {
+        UNKNOWN_MIMETYPE
+    } else {
+        OCTET_STREAM_MIMETYPE
+    };
+    let (mimetype, bytes_read) = sniff(path, options, mimetype_of)?;
+    Ok((mimetype.unwrap_or_else(|| fallback.to_string()), bytes_read))
+}
+
+/// The extension that the content of `path` suggests, e.g. `png`, or an empty string if
+/// the content isn't recognized.
+fn sniffed_extension(path: &Path, options: &ScanOptions) -> Result<String> {
+    let (ext, _) = sniff(path, options, |bytes| {
+        infer::get(bytes).map(|kind| kind.extension().to_string())
+    })?;
+    Ok(ext.unwrap_or_default())
+}
+
+/// The extension `path` should have, given that its content was identified as `mimetype`,
+/// or `None` if `ext` already fits.
+///
+/// Only a contradiction counts: if the content isn't recognized, or `ext` is missing or
+/// unknown to `mime_guess`, there is nothing to go on and the file is left alone. So is a
+/// file that was only recognized as a generic container, since a `.docx` is a zip archive
+/// and a `.doc` an OLE compound file.
+fn suggested_extension(
+    path: &Path,
+    ext: &str,
+    mimetype: &str,
+    options: &ScanOptions,
+) -> Result<Option<String>> {
+    let inconclusive = [
+        EMPTY_MIMETYPE,
+        OCTET_STREAM_MIMETYPE,
+        UNKNOWN_MIMETYPE,
+        "application/zip",
+        "application/x-ole-storage",
+    ];
+    if ext.is_empty() || inconclusive.contains(&mimetype) {
+        return Ok(None);
+    }
+    let expected = mime_guess::from_ext(ext);
+    if expected.is_empty()
+        || expected
+            .iter_raw()
+            .any(|candidate| candidate.eq_ignore_ascii_case(mimetype))
+    {
+        return Ok(None);
+    }
+    let suggested = sniffed_extension(path, options)?;
+    Ok((!suggested.is_empty() && !suggested.eq_ignore_ascii_case(ext)).then_some(suggested))
+}
+
+/// Runs `identify` on the first `--sniff-bytes` bytes of `path`, returning what it found
+/// along with how many bytes it was given.
+///
+/// As long as `identify` finds nothing and there is more of the file, the window doubles
+/// and `identify` runs again, up to `--sniff-max-bytes`. Bytes already read are kept, so
+/// every byte is only read once.
+///
+/// A panic in `identify`, say a detector tripping over malformed content, becomes an error
+/// for this one file instead of taking the whole scan down.
+fn sniff<T>(
+    path: &Path,
+    options: &ScanOptions,
+    identify: impl Fn(&[u8]) -> Option<T>,
+) -> Result<(Option<T>, usize)> {
+    let mut file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let mut window = options.sniff_bytes.unwrap_or(SNIFF_WINDOW).max(1);
+    let max_window = options.sniff_max_bytes.unwrap_or(window).max(window);
+    let mapped = if options.mmap {
+        map_sniff_window(&file, max_window)
+    } else {
+        None
+    };
+    let mut buffer = Vec::new();
+    loop {
+        let bytes = match &mapped {
+            Some(map) => &map[..window.min(map.len())],
+            None => {
+                let missing = (window - buffer.len()) as u64;
+                (&mut file)
+                    .take(missing)
+                    .read_to_end(&mut buffer)
+                    .with_context(|| format!("failed to read {:?}", path))?;
+                &buffer[..]
+            }
+        };
+        let found =
+            std::panic::catch_unwind(AssertUnwindSafe(|| identify(bytes))).map_err(|payload| {
+                let reason = payload
+                    .downcast_ref::<&str>()
+                    .map(|reason| reason.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                anyhow::anyhow!("content detection panicked on {:?}: {reason}", path)
+            })?;
+        // Fewer bytes than asked for means the whole file has been looked at.
+        if found.is_some() || bytes.len() < window || window == max_window {
+            return Ok((found, bytes.len()));
+        }
+        window = window.saturating_mul(2).min(max_window);
+    }
+}
+
+/// Mimetype reported for empty files, as `file --mime-type` does.
+const EMPTY_MIMETYPE: &str = "inode/x-empty";
+
+/// Mimetype reported for content that isn't recognized.
+const OCTET_STREAM_MIMETYPE: &str = "application/octet-stream";
+
+/// Mimetype reported for unrecognized content with `--no-mime-fallback`, so it isn't
+/// mistaken for content that is known to be binary.
+const UNKNOWN_MIMETYPE: &str = "unknown";
+
+/// Mimetype reported for links to directories that aren't followed, which have no content
+/// to sniff.
+const DIRECTORY_MIMETYPE: &str = "inode/directory";
+
+/// Identifies content from its leading `bytes`, or returns `None` if it isn't recognized.
+///
+/// No bytes at all means the file is empty, which is worth telling apart from content
+/// that simply isn't recognized.
+fn mimetype_of(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        return Some(EMPTY_MIMETYPE.to_string());
+    }
+    infer::get(bytes).map(|kind| kind.mime_type().to_string())
+}
+
+/// The bytes that files of a mimetype always end with, for the formats `--check-integrity`
+/// knows about.
+const END_MARKERS: [(&str, &[u8]); 3] = [
+    ("image/jpeg", &[0xFF, 0xD9]),
+    // The IEND chunk: its type and CRC, after a zero length.
+    ("image/png", b"IEND\xAE\x42\x60\x82"),
+    ("image/gif", &[0x3B]),
+];
+
+/// Whether `path`, whose content was identified as `mimetype`, lacks the end marker of its
+/// format. Formats without a known end marker are never considered truncated.
+///
+/// This only looks at the last few bytes, so it catches cut-off downloads, not corruption in
+/// the middle of a file.
+fn is_truncated(path: &Path, mimetype: &str) -> Result<bool> {
+    let Some((_, marker)) = END_MARKERS.iter().find(|(known, _)| *known == mimetype) else {
+        return Ok(false);
+    };
+    let mut file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let len = file
+        .metadata()
+        .with_context(|| format!("failed to stat {:?}", path))?
+        .len();
+    let marker_len = marker.len() as u64;
+    if len < marker_len {
+        return Ok(true);
+    }
+    file.seek(SeekFrom::Start(len - marker_len))
+        .with_context(|| format!("failed to seek {:?}", path))?;
+    let mut tail = vec![0u8; marker.len()];
+    file.read_exact(&mut tail)
+        .with_context(|| format!("failed to read {:?}", path))?;
+    Ok(tail != *marker)
+}
+
+/// Memory-maps the sniff window at the start of `file`.
+///
+/// Returns `None` for empty files, which can't be mapped, and whenever mapping fails, so
+/// callers can fall back to a plain read.
+fn map_sniff_window(file: &File, max_window: usize) -> Option<Mmap> {
+    let len = file.metadata().ok()?.len();
+    if len == 0 {
+        return None;
+    }
+    let window = usize::try_from(len).map_or(max_window, |len| len.min(max_window));
+    // SAFETY: the mapping is read-only and only lives for the duration of one sniff. If
+    // another process truncates the file while it is mapped, reading the mapping raises
+    // SIGBUS; that risk is why mapping is opt-in via `--mmap`.
+    unsafe { MmapOptions::new().len(window).map(file) }.ok()
+}
+
+/// Brings `key` into Unicode NFC if requested, so NFD names (as produced on macOS) and NFC
+/// names land in the same group.
+fn group_key(key: String, options: &ScanOptions) -> String {
+    if options.normalize_unicode {
+        key.nfc().collect()
+    } else {
+        key
+    }
+}
+
+/// The bucket an extensionless `path` is counted under with
+/// `--report-empty-extensions-separately`. `Path::extension` returns an empty extension for
+/// `foo.` but none at all for both `foo` and `.bashrc`, so dotfiles are told apart by name.
+fn empty_extension_bucket(path: &Path) -> &'static str {
+    let name = path.file_name().unwrap_or_default().as_encoded_bytes();
+    if path.extension().is_some() {
+        "(empty)"
+    } else if name.starts_with(b".") {
+        "(dotfile)"
+    } else {
+        "(none)"
+    }
+}
+
+/// Turns a detected mimetype into the key it is tallied under, cutting it down to the
+/// major type (`image/png` becomes `image`) with `--collapse-mime-subtypes`.
+fn mimetype_key(mut mimetype: String, options: &ScanOptions) -> String {
+    if options.collapse_mime_subtypes
+        && let Some(slash) = mimetype.find('/')
+    {
+        mimetype.truncate(slash);
+    }
+    group_key(mimetype, options)
+}
+
+/// What a file contributes to a report, gathered before anything is tallied.
+#[derive(Debug, Clone, Default)]
+struct FileInfo {
+    /// The extension as found on disk.
+    ext: String,
+    /// Key the file is counted under unless `--group-regex` matches its name.
+    ext_key: String,
+    mimetype: Option<String>,
+    /// Length as reported by the filesystem, even for files that don't add to sizes; for
+    /// links, the length of their target, unless that is a directory.
+    len: u64,
+    kind: FileKind,
+    modified: Option<SystemTime>,
+    accessed: Option<SystemTime>,
+    /// Owning uid, on platforms that have one.
+    owner: Option<u32>,
+}
+
+/// What a non-directory entry is, as far as the size total is concerned.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum FileKind {
+    #[default]
+    Regular,
+    /// Symlinks, and on Windows also junctions: the standard library reports every
+    /// name-surrogate reparse point as a link, so junctions are left out of sizes, only
+    /// descended into with `--follow-symlinks` and caught by the walk's loop check like
+    /// symlinks are.
+    Symlink,
+    /// FIFOs, sockets and device nodes.
+    Special,
+}
+
+/// One entry of a scan: a directory, or anything else, which is tallied as a file.
+trait ScanEntry {
+    fn path(&self) -> &Path;
+    /// Distance from the scan target, which itself sits at depth 0.
+    fn depth(&self) -> usize;
+    fn is_dir(&self) -> bool;
+    /// Finds out everything [`tally_file`] needs to know about a non-directory entry. Slow
+    /// sniffs and the bytes read for sniffing are noted in `report` straight away, even if
+    /// inspecting fails afterwards.
+    fn inspect(&self, report: &mut Report, options: &ScanOptions) -> Result<FileInfo>;
+}
+
+/// Where the entries of a scan come from: a walk of the filesystem, or in tests a plain list
+/// of made-up entries, so the tallying can be exercised without touching the disk.
+trait EntrySource {
+    type Entry: ScanEntry + Sync;
+
+    /// The entries in walk order, every directory before its contents.
+    fn entries(self) -> impl Iterator<Item = Result<Self::Entry, ScanError>>;
+}
+
+impl ScanEntry for walkdir::DirEntry {
+    fn path(&self) -> &Path {
+        walkdir::DirEntry::path(self)
+    }
+
+    fn depth(&self) -> usize {
+        walkdir::DirEntry::depth(self)
+    }
+
+    fn is_dir(&self) -> bool {
+        // The entry's own file type honours `follow_symlinks`, unlike `Path::is_dir`, which
+        // would count a link to a directory as a folder either way.
+        self.file_type().is_dir()
+    }
+
+    fn inspect(&self, report: &mut Report, options: &ScanOptions) -> Result<FileInfo> {
+        // Filenames are arbitrary bytes on Unix; invalid UTF-8 shows up as U+FFFD rather than
+        // silently dropping the file into the empty-extension bucket.
+        let ext = self
+            .path()
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let metadata = self
+            .path()
+            .metadata()
+            .with_context(|| format!("failed to read metadata for {:?}", self.path()))?;
+
+        // Only a link to a directory that isn't followed gets here as a directory.
+        let is_dir_link = metadata.is_dir();
+        let mimetype = if options.extensions_only {
+            None
+        } else if is_dir_link {
+            Some(mimetype_key(DIRECTORY_MIMETYPE.to_string(), options))
+        } else if options.mime_from_ext {
+            let mimetype = mime_guess::from_ext(&ext)
+                .first_raw()
+                .unwrap_or(OCTET_STREAM_MIMETYPE);
+            Some(mimetype_key(mimetype.to_string(), options))
+        } else {
+            let started = options.warn_slow_files.map(|_| Instant::now());
+            let detected = detect_mimetype(self.path(), options);
+            let elapsed = started.map(|started| started.elapsed());
+            if let (Some(threshold), Some(elapsed)) = (options.warn_slow_files, elapsed)
+                && elapsed > threshold
+            {
+                eprintln!(
+                    "warning: sniffing {:?} took {} ms",
+                    self.path(),
+                    elapsed.as_millis()
+                );
+                report.slow_files.push(SlowFile {
+                    path: self.path().to_path_buf(),
+                    elapsed,
+                });
+            }
+            let (mimetype, bytes_read) = detected
+                .with_context(|| format!("failed to detect mimetype for {:?}", self.path()))?;
+            report.sniffed_files += 1;
+            report.bytes_read += bytes_read as u64;
+            if options.check_integrity
+                && is_truncated(self.path(), &mimetype)
+                    .with_context(|| format!("failed to check {:?}", self.path()))?
+            {
+                report.suspect_files.push(self.path().to_path_buf());
+            }
+            if options.suggest_extensions
+                && let Some(suggested_ext) =
+                    suggested_extension(self.path(), &ext, &mimetype, options)
+                        .with_context(|| format!("failed to sniff {:?}", self.path()))?
+            {
+                report.misnamed_files.push(MisnamedFile {
+                    path: self.path().to_path_buf(),
+                    suggested_ext,
+                });
+            }
+            Some(mimetype_key(mimetype, options))
+        };
+        let ext_key = if options.merge_extensionless_by_magic
+            && !options.mimetypes_only
+            && ext.is_empty()
+            && !is_dir_link
+        {
+            sniffed_extension(self.path(), options)
+                .with_context(|| format!("failed to sniff {:?}", self.path()))?
+        } else {
+            ext.clone()
+        };
+        let ext_key = if options.split_empty_extensions && ext_key.is_empty() && !is_dir_link {
+            empty_extension_bucket(self.path()).to_string()
+        } else {
+            ext_key
+        };
+
+        // A followed link reports the file type of its target, so ask about the path itself.
+        let kind = if self.path_is_symlink() {
+            FileKind::Symlink
+        } else if self.file_type().is_file() {
+            FileKind::Regular
+        } else {
+            FileKind::Special
+        };
+        #[cfg(unix)]
+        let owner = {
+            use std::os::unix::fs::MetadataExt;
+            Some(metadata.uid())
+        };
+        #[cfg(not(unix))]
+        let owner = None;
+
+        Ok(FileInfo {
+            ext,
+            ext_key,
+            mimetype,
+            // A directory's length says nothing about its contents.
+            len: if is_dir_link { 0 } else { metadata.len() },
+            kind,
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+            owner,
+        })
+    }
+}
+
+/// The filesystem below `target`, as walked by `walkdir`.
+struct WalkSource<'a> {
+    target: &'a Path,
+    options: &'a ScanOptions,
+    /// Last path tallied by an interrupted scan; it and everything before it is skipped.
+    resume_after: Option<&'a Path>,
+}
+
+impl EntrySource for WalkSource<'_> {
+    type Entry = walkdir::DirEntry;
+
+    fn entries(self) -> impl Iterator<Item = Result<walkdir::DirEntry, ScanError>> {
+        // A file target is the only entry there is, so it is always tallied.
+        let skip_root = usize::from(!self.options.include_root && self.target.is_dir());
+        let mut walker = WalkDir::new(self.target).follow_links(self.options.follow_symlinks);
+        if self.options.resume_file.is_some() {
+            walker = walker.sort_by_file_name();
+        }
+        let resume_after = self.resume_after;
+        // Sorted walks visit paths in `Path` order, so everything up to the checkpoint has
+        // already been tallied.
+        let options = self.options;
+        let root = std::fs::canonicalize(self.target).unwrap_or_else(|_| self.target.into());
+        let by_flags = move |entry: &walkdir::DirEntry| match exclusion(entry, &root, options) {
+            Some(reason) => {
+                if options.explain {
+                    explain(entry.path(), &reason);
+                }
+                false
+            }
+            None => true,
+        };
+        walker
+            .into_iter()
+            .filter_entry(move |entry| {
+                by_flags(entry)
+                    && options
+                        .filter
+                        .as_ref()
+                        .is_none_or(|EntryFilter(keep)| keep(entry))
+            })
+            .skip(skip_root)
+            .filter(move |entry| {
+                let path = match entry {
+                    Ok(entry) => Some(entry.path()),
+                    Err(e) => e.path(),
+                };
+                !matches!((resume_after, path), (Some(last), Some(path)) if path <= last)
+            })
+            .map(|entry| {
+                entry.map_err(|e| ScanError {
+                    path: e.path().map(|p| p.to_path_buf()).unwrap_or_default(),
+                    message: format!("failed to read entry: {e}"),
+                    kind: e
+                        .io_error()
+                        .map_or(ScanErrorKind::Other, ScanErrorKind::of_io),
+                })
+            })
+    }
+}
+
+/// The rule that excludes `entry` from the walk, if `--exclude-dir` or `--exclude-hidden-dirs`
+/// does. Its ancestors have already passed, so only its own name needs checking, plus, for a
+/// followed link, where it really leads: a link into an excluded directory would otherwise
+/// smuggle that directory's contents back in. Only the part of the link's target below the
+/// canonical scan `root` is judged, so scanning from inside, say, a `target` directory doesn't
+/// exclude every link.
+fn exclusion(entry: &walkdir::DirEntry, root: &Path, options: &ScanOptions) -> Option<String> {
+    if entry.depth() == 0 {
+        return None;
+    }
+    let is_dir = entry.file_type().is_dir();
+    if options.exclude_hidden_dirs
+        && is_dir
+        && entry.file_name().as_encoded_bytes().starts_with(b".")
+    {
+        return Some("skipped by --exclude-hidden-dirs".to_string());
+    }
+    if options.exclude_dirs.is_empty() {
+        return None;
+    }
+    let excluded_name = |name: &std::ffi::OsStr| options.exclude_dirs.iter().any(|dir| dir == name);
+    if is_dir && excluded_name(entry.file_name()) {
+        return Some(format!("skipped by --exclude-dir {:?}", entry.file_name()));
+    }
+    if !options.follow_symlinks || !entry.path_is_symlink() {
+        return None;
+    }
+    let resolved = std::fs::canonicalize(entry.path()).ok()?;
+    let resolved = resolved.strip_prefix(root).unwrap_or(&resolved);
+    resolved.components().find_map(|component| match component {
+        Component::Normal(name) if excluded_name(name) => Some(format!(
+            "skipped by --exclude-dir {name:?}, which the link leads into"
+        )),
+        _ => None,
+    })
+}
+
+/// Prints the `--explain` verdict on `path`.
+fn explain(path: &Path, verdict: &str) {
+    eprintln!("{}: {verdict}", path.display());
+}
+
+/// Tallies the file `entry` into `report`, returning its `--explain` verdict.
+///
+/// This runs on worker threads in a parallel scan, so the verdict is left to the caller to
+/// print once the file's fate is settled.
+fn process_entry(
+    entry: &impl ScanEntry,
+    report: &mut Report,
+    options: &ScanOptions,
+) -> Result<&'static str> {
+    // Checked before inspecting, so ignored files aren't even opened.
+    if let Some(whitelist) = &options.ext_whitelist {
+        let ext = entry.path().extension().unwrap_or_default();
+        if !whitelist.contains(&ext.to_string_lossy().to_lowercase()) {
+            return Ok("skipped, extension not in --ext-whitelist-from");
+        }
+    }
+    let info = entry.inspect(report, options)?;
+    // Only tally once everything about the file is known, so a failure halfway through
+    // can't leave the totals out of sync with each other.
+    tally_file(report, entry.path(), entry.depth(), info, options);
+    Ok("counted")
+}
+
+/// Adds a file at `path`, `depth` levels below the target, to every tally of `report`.
+fn tally_file(
+    report: &mut Report,
+    path: &Path,
+    depth: usize,
+    info: FileInfo,
+    options: &ScanOptions,
+) {
+    // Only regular files add to the sizes: a symlink's target is counted where it lives (or
+    // not at all if it's outside the tree), and special files have no meaningful length.
+    let size = match info.kind {
+        FileKind::Regular => info.len,
+        FileKind::Symlink if options.count_symlinks_as_files => info.len,
+        FileKind::Symlink => {
+            report.excluded_symlinks += 1;
+            0
+        }
+        FileKind::Special => {
+            report.excluded_special_files += 1;
+            0
+        }
+    };
+    report.size += size;
+    let sized = info.kind == FileKind::Regular
+        || info.kind == FileKind::Symlink && options.count_symlinks_as_files;
+    if let Some(scheme) = options.histogram
+        && sized
+    {
+        let bucket = scheme.bucket(size);
+        if report.size_histogram.len() <= bucket {
+            report.size_histogram.resize(bucket + 1, 0);
+        }
+        report.size_histogram[bucket] += 1;
+    }
+    if let Some(cutoff) = options.cold_before
+        && sized
+    {
+        let touched = match options.cold_by {
+            ColdBy::Mtime => info.modified,
+            ColdBy::Atime => info.accessed,
+        };
+        if touched.is_some_and(|time| time < cutoff) {
+            report.cold_files += 1;
+            report.cold_size += size;
+        }
+    }
+    if options.owner_sizes
+        && let Some(owner) = info.owner
+    {
+        *report.owner_sizes.entry(owner).or_default() += size;
+    }
+    if let Some(limit) = options.recent
+        && let Some(modified) = info.modified
+    {
+        let file = RecentFile {
+            modified,
+            path: path.to_path_buf(),
+            size,
+        };
+        report.push_recent(file, limit);
+    }
+    if options.include_files.is_some() {
+        report.file_records.push(FileRecord {
+            path: path.to_path_buf(),
+            ext: info.ext.clone(),
+            mime: info.mimetype.clone(),
+            size,
+        });
+    }
+    // Judged on the raw extension, before any normalization touches it.
+    let has_upper = info.ext.chars().any(char::is_uppercase);
+    let has_lower = info.ext.chars().any(char::is_lowercase);
+    if has_upper && has_lower {
+        report.mixedcase_ext_files += 1;
+    } else if has_upper {
+        report.uppercase_ext_files += 1;
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    if options.name_counts {
+        let name = report.file_names.entry(file_name.to_string()).or_default();
+        name.0 += 1;
+        name.1 += size;
+    }
+    let key = options
+        .group_regex
+        .as_ref()
+        .and_then(|group_regex| group_regex.key(&file_name))
+        .unwrap_or(info.ext_key);
+    let key = group_key(key, options);
+    if options.depth_counts {
+        let counts = report.ext_depths.entry(key.clone()).or_default();
+        let level = depth.saturating_sub(1);
+        if counts.len() <= level {
+            counts.resize(level + 1, 0);
+        }
+        counts[level] += 1;
+    }
+    // A file at depth `d` sits in a directory at depth `d - 1`; deeper ones are rolled up.
+    if let Some(rollup_depth) = options.rollup_depth
+        && let Some(dir) = path
+            .ancestors()
+            .nth(depth.saturating_sub(rollup_depth).max(1))
+    {
+        *report
+            .rollup
+            .entry(dir.to_path_buf())
+            .or_default()
+            .entry(key.clone())
+            .or_default() += 1;
+    }
+    if !options.mimetypes_only {
+        report
+            .extensions
+            .entry(key)
+            .and_modify(|e| *e += 1)
+            .or_insert(1);
+    }
+    if let Some(mimetype) = info.mimetype {
+        *report.mimetype_sizes.entry(mimetype.clone()).or_default() += size;
+        report
+            .mimetypes
+            .entry(mimetype)
+            .and_modify(|e| *e += 1)
+            .or_insert(1);
+    }
+    if options.dir_file_counts
+        && let Some(parent) = path.parent()
+    {
+        *report
+            .dir_file_counts
+            .entry(parent.to_path_buf())
+            .or_default() += 1;
+    }
+    // Depth 1 is a direct child of the target, so a file at depth `d` belongs to the
+    // top-level directory `d - 1` levels up.
+    if options.top_level_sizes
+        && depth >= 2
+        && let Some(top_level) = path.ancestors().nth(depth - 1)
+    {
+        let sizes = report
+            .top_level_dirs
+            .entry(top_level.to_path_buf())
+            .or_default();
+        sizes.recursive_size += size;
+        sizes.files += 1;
+        if depth == 2 {
+            sizes.shallow_size += size;
+        }
+    }
+}
+
+/// Walks `target` and tallies everything found below it.
+///
+/// Errors are collected into the report and the walk carries on, unless `options.strict` is
+/// set, in which case the first error aborts the scan.
+///
+/// With `options.resume_file`, the walk goes in file name order and progress is saved every
+/// [`resume::CHECKPOINT_INTERVAL`] entries, so an interrupted scan can pick up after the last
+/// checkpointed path. The file is removed once the scan completes.
+fn scan(target: PathBuf, options: &ScanOptions) -> Result<Report> {
+    let checkpoint = match &options.resume_file {
+        // Which real files were counted, and the names seen in every directory, would have to
+        // be saved as well, and there can be one for every file in the tree.
+        Some(_) if options.follow_symlinks => {
+            bail!("--resume-file can't be combined with --follow-symlinks")
+        }
+        Some(_) if options.case_collisions => {
+            bail!("--resume-file can't be combined with --case-collisions")
+        }
+        Some(resume_file) => resume::load(resume_file, &target)?,
+        None => None,
+    };
+    let resume_after = checkpoint.as_ref().map(|c| c.last_path.clone());
+    let source = WalkSource {
+        target: &target,
+        options,
+        resume_after: resume_after.as_deref(),
+    };
+    scan_entries(source, &target, checkpoint, options)
+}
+
+/// Tallies everything `source` yields into a report, starting from `checkpoint` if given;
+/// the heart of [`scan`]. `target` only names the scan in messages and checkpoints.
+fn scan_entries<S: EntrySource>(
+    source: S,
+    target: &Path,
+    checkpoint: Option<resume::Checkpoint>,
+    options: &ScanOptions,
+) -> Result<Report> {
+    // Directories found to be unreadable, here or before the checkpoint. Everything below one
+    // would only fail the same way, so it is skipped instead of adding an error per entry.
+    let (mut report, mut unreadable_dirs) = match checkpoint {
+        Some(checkpoint) => (checkpoint.report, checkpoint.unreadable_dirs),
+        None => (Report::default(), BTreeSet::new()),
+    };
+    let pb = if options.progress_bar {
+        let progress = ProgressBar::new_spinner();
+        progress.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed}] {wide_msg}")
+                .expect("failed to set progress style"),
+        );
+        progress.set_message("Scanning target...");
+        Some(progress)
+    } else {
+        None
+    };
+
+    let pool = if options.threads > 1 {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.threads)
+            .build()
+            .context("failed to start worker threads")?;
+        Some(pool)
+    } else {
+        None
+    };
+    // Serially, every entry is a batch of its own, so checks happen after each one.
+    let batch_size = match pool {
+        Some(_) => options.threads * PARALLEL_BATCH_PER_THREAD,
+        None => 1,
+    };
+
+    // The directories the walk is still inside, innermost last, with the names seen in them.
+    let mut open_dirs: Vec<OpenDir> = Vec::new();
+    // Where the files counted so far really live, when links are followed.
+    let mut real_paths: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut walked = 0;
+    let mut last_heartbeat = Instant::now();
+    let mut entries = source.entries();
+    loop {
+        let batch: Vec<_> = entries.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            break;
+        }
+        let walked_before = walked;
+        let mut last_path = None;
+        // The batch's files, to be processed together, with their positions in the batch.
+        let mut files = Vec::new();
+        let mut file_positions = Vec::new();
+        let mut walk_errors = Vec::new();
+        // `--explain` verdicts by position, printed together once the batch is settled.
+        let mut verdicts = Vec::new();
+        for (position, entry) in batch.into_iter().enumerate() {
+            if !unreadable_dirs.is_empty() {
+                let path = match &entry {
+                    Ok(entry) => entry.path(),
+                    Err(e) => e.path.as_path(),
+                };
+                if path
+                    .ancestors()
+                    .skip(1)
+                    .any(|dir| unreadable_dirs.contains(dir))
+                {
+                    continue;
+                }
+            }
+            // Followed links can lead to the same file under several paths; only the first
+            // counts.
+            if options.follow_symlinks
+                && let Ok(entry) = &entry
+                && !entry.is_dir()
+                && let Ok(real_path) = std::fs::canonicalize(entry.path())
+                && let Some(real_path) = real_paths.replace(real_path)
+            {
+                if options.explain {
+                    verdicts.push((
+                        position,
+                        entry.path().to_path_buf(),
+                        format!("skipped, already counted as {}", real_path.display()),
+                    ));
+                }
+                report.collapsed_link_paths += 1;
+                continue;
+            }
+            if let Some(interval) = options.heartbeat
+                && last_heartbeat.elapsed() >= interval
+            {
+                eprintln!("{}", heartbeat_message(&report));
+                last_heartbeat = Instant::now();
+            }
+
+            if options.case_collisions
+                && let Ok(entry) = &entry
+                && let (Some(parent), Some(name)) =
+                    (entry.path().parent(), entry.path().file_name())
+            {
+                close_dirs(&mut open_dirs, entry.depth(), &mut report.case_collisions);
+                if open_dirs.last().is_none_or(|dir| dir.path != parent) {
+                    open_dirs.push(OpenDir {
+                        path: parent.to_path_buf(),
+                        depth: entry.depth().saturating_sub(1),
+                        names: BTreeMap::new(),
+                    });
+                }
+                if let Some(dir) = open_dirs.last_mut() {
+                    dir.names
+                        .entry(name.to_string_lossy().to_lowercase())
+                        .or_default()
+                        .push(name.to_os_string());
+                }
+            }
+            if options.dir_entry_counts
+                && let Ok(entry) = &entry
+            {
+                report.count_dir_entry(entry.path(), entry.depth());
+            }
+
+            let entry_path = match &entry {
+                Ok(entry) => Some(entry.path().to_path_buf()),
+                Err(e) => (!e.path.as_os_str().is_empty()).then(|| e.path.clone()),
+            };
+            if entry_path.is_some() {
+                last_path = entry_path;
+            }
+
+            match entry {
+                Ok(entry) if entry.is_dir() => {
+                    if options.top_level_sizes && entry.depth() == 1 {
+                        report
+                            .top_level_dirs
+                            .entry(entry.path().to_path_buf())
+                            .or_default();
+                    }
+                    report.folders.push(entry.path().to_path_buf());
+                }
+                Ok(entry) => {
+                    if let Some(ref progress) = pb {
+                        progress.set_message(format!("Processing: {}", entry.path().display()));
+                        progress.tick();
+                    }
+                    files.push(entry);
+                    file_positions.push(position);
+                }
+                Err(error) => {
+                    if error.kind == ScanErrorKind::PermissionDenied
+                        && !error.path.as_os_str().is_empty()
+                    {
+                        unreadable_dirs.insert(error.path.clone());
+                    }
+                    walk_errors.push((position, Either::Left(error)));
+                }
+            }
+            walked += 1;
+        }
+
+        let outcomes = process_files(&files, &mut report, options, pool.as_ref());
+        let mut pending = walk_errors;
+        for ((entry, position), outcome) in files.iter().zip(file_positions).zip(outcomes) {
+            match outcome {
+                Ok(verdict) if options.explain => {
+                    verdicts.push((position, entry.path().to_path_buf(), verdict.to_string()));
+                }
+                Ok(_) => {}
+                Err(e) => pending.push((position, Either::Right((entry, e)))),
+            }
+        }
+        verdicts.sort_by_key(|(position, _, _)| *position);
+        for (_, path, verdict) in verdicts {
+            explain(&path, &verdict);
+        }
+        // Handled in walk order, so `--strict` stops at the first error a serial walk meets.
+        pending.sort_by_key(|(position, _)| *position);
+        for (_, error) in pending {
+            let error = match error {
+                Either::Left(error) => error,
+                Either::Right((entry, e)) => {
+                    match file_error(entry.path(), &e, &mut unreadable_dirs) {
+                        Some(error) => error,
+                        None => continue,
+                    }
+                }
+            };
+            if options.strict {
+                if let Some(progress) = pb {
+                    progress.abandon_with_message("Aborted");
+                }
+                bail!("{}: {}", error.path.display(), error.message);
+            }
+            report.push_error(error, options.max_errors);
+        }
+
+        if let Some(limit) = options.include_files
+            && report.file_records.len() > limit
+        {
+            if let Some(progress) = pb {
+                progress.abandon_with_message("Aborted");
+            }
+            bail!(
+                "--include-files: more than {limit} files under {}, raise --include-files-limit to list them anyway",
+                target.display()
+            );
+        }
+
+        if options
+            .limit_bytes
+            .is_some_and(|limit| report.size >= limit)
+        {
+            report.truncated = true;
+            break;
+        }
+
+        if let (Some(resume_file), Some(path)) = (&options.resume_file, &last_path)
+            && walked / resume::CHECKPOINT_INTERVAL > walked_before / resume::CHECKPOINT_INTERVAL
+        {
+            resume::save(resume_file, target, path, &report, &unreadable_dirs)?;
+        }
+    }
+
+    close_dirs(&mut open_dirs, 0, &mut report.case_collisions);
+    report.case_collisions.sort();
+
+    if let Some(resume_file) = &options.resume_file
+        && resume_file.exists()
+    {
+        std::fs::remove_file(resume_file)
+            .with_context(|| format!("failed to remove {:?}", resume_file))?;
+    }
+
+    if let Some(progress) = pb {
+        progress.finish_with_message(format!("Completed with {} errors", report.error_count()));
+    }
+
+    Ok(report)
+}
+
+/// A directory the walk is inside of, for `--case-collisions`.
+struct OpenDir {
+    path: PathBuf,
+    /// Depth of the directory itself; its entries are one level deeper.
+    depth: usize,
+    /// Names of the entries seen so far, by their lowercased form.
+    names: BTreeMap<String, Vec<OsString>>,
+}
+
+/// Closes the directories in `open_dirs` that a walk arriving at `depth` has left, adding
+/// the names in them that only differ by case to `collisions`.
+///
+/// Walks are depth first, so once an entry is no deeper than a directory, nothing more of
+/// that directory is to come.
+fn close_dirs(open_dirs: &mut Vec<OpenDir>, depth: usize, collisions: &mut Vec<Vec<PathBuf>>) {
+    while let Some(dir) = open_dirs.pop_if(|dir| dir.depth >= depth) {
+        collisions.extend(
+            dir.names
+                .into_values()
+                .filter(|names| names.len() > 1)
+                .map(|names| {
+                    names
+                        .iter()
+                        .map(|name| dir.path.join(name))
+                        .sorted()
+                        .collect()
+                }),
+        );
+    }
+}
+
+/// The error to record for the file at `path` that failed with `e`, or `None` if it lies in a
+/// directory already known to be unreadable.
+///
+/// Not even being allowed to look the file up means its directory can't be searched, and
+/// neither can any of its other entries, so the error is the directory's, which is then
+/// added to `unreadable_dirs`.
+fn file_error(
+    path: &Path,
+    e: &anyhow::Error,
+    unreadable_dirs: &mut BTreeSet<PathBuf>,
+) -> Option<ScanError> {
+    if path
+        .ancestors()
+        .skip(1)
+        .any(|dir| unreadable_dirs.contains(dir))
+    {
+        return None;
+    }
+    let kind = ScanErrorKind::of(e);
+    if kind == ScanErrorKind::PermissionDenied
+        && let Err(lookup) = std::fs::symlink_metadata(path)
+        && lookup.kind() == io::ErrorKind::PermissionDenied
+        && let Some(parent) = path.parent()
+    {
+        unreadable_dirs.insert(parent.to_path_buf());
+        return Some(ScanError {
+            path: parent.to_path_buf(),
+            message: format!("failed to look up entries: {lookup}"),
+            kind,
+        });
+    }
+    Some(ScanError {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+        kind,
+    })
+}
+
+/// Writes `report` to the file at `path` in `format`, keeping the remaining display options.
+fn write_report_file(
+    report: &Report,
+    path: &Path,
+    format: OutputFormat,
+    options: &DisplayOptions,
+) -> Result<()> {
+    let options = DisplayOptions {
+        format,
+        flatten: false,
+        print0_errors: false,
+        ..options.clone()
+    };
+    let mut file = io::BufWriter::new(
+        File::create(path).with_context(|| format!("failed to create {:?}", path))?,
+    );
+    report
+        .display(&mut file, &options)
+        .and_then(|()| file.flush())
+        .with_context(|| format!("failed to write {:?}", path))
+}
+
+/// Writes `report` to the file at `path` rather than stdout. With `gzip`, the output is
+/// compressed as it's written, and `.gz` is added to the file name unless it's already there.
+fn write_output_file(
+    report: &Report,
+    path: &Path,
+    gzip: bool,
+    options: &DisplayOptions,
+) -> Result<()> {
+    let path = if gzip && path.extension().is_none_or(|ext| ext != "gz") {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".gz");
+        PathBuf::from(name)
+    } else {
+        path.to_path_buf()
+    };
+    let mut file = io::BufWriter::new(
+        File::create(&path).with_context(|| format!("failed to create {:?}", path))?,
+    );
+    let written = if gzip {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        report
+            .display(&mut encoder, options)
+            .and_then(|()| encoder.finish())
+            .and_then(|mut file| file.flush())
+    } else {
+        report
+            .display(&mut file, options)
+            .and_then(|()| file.flush())
+    };
+    written.with_context(|| format!("failed to write {:?}", path))
+}
+
+/// Drops the first `count` named components of `path`; a leading `/` doesn't count as one,
+/// just like with `tar --strip-components`. If that would leave nothing, the file name is kept.
+fn strip_components(path: &Path, count: usize) -> &Path {
+    let mut components = path.components();
+    let mut stripped = 0;
+    while stripped < count {
+        match components.next() {
+            Some(Component::Normal(_) | Component::ParentDir) => stripped += 1,
+            Some(Component::Prefix(_) | Component::RootDir | Component::CurDir) => {}
+            None => break,
+        }
+    }
+    let rest = components.as_path();
+    if rest.as_os_str().is_empty() {
+        path.file_name().map_or(path, Path::new)
+    } else {
+        rest
+    }
+}
+
+/// The line printed by `--heartbeat`, e.g. `still scanning: 1200 files, 340 GiB so far`.
+fn heartbeat_message(report: &Report) -> String {
+    format!(
+        "still scanning: {}, {} so far",
+        counted(report.file_count(), "file"),
+        friendly_bytes(report.size, DEFAULT_PRECISION)
+    )
+}
+
+/// Formats a byte count with binary units, e.g. `1.32 KiB` at a precision of 2.
+///
+/// Plain bytes are always whole numbers; `precision` only applies from KiB upwards.
+fn friendly_bytes(bytes: u64, precision: usize) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.precision$} {}", UNITS[unit])
+}
+
+/// Formats `time` in the given format; `now` is the reference point for relative times.
+fn format_time(time: SystemTime, format: TimeFormat, now: SystemTime) -> String {
+    match format {
+        TimeFormat::Iso8601 => format_timestamp(time),
+        TimeFormat::Unix => time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+            .to_string(),
+        TimeFormat::Relative => match now.duration_since(time) {
+            Ok(age) => format!("{} ago", format_age(age.as_secs())),
+            Err(e) => format!("in {}", format_age(e.duration().as_secs())),
+        },
+    }
+}
+
+/// `count` followed by `unit`, pluralized unless there is exactly one, e.g. `1 file`.
+fn counted(count: usize, unit: &str) -> String {
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural}")
+}
+
+/// Spells out a number of seconds in the largest unit that fits, e.g. `3 days`.
+fn format_age(secs: u64) -> String {
+    const UNITS: [(u64, &str); 5] = [
+        (365 * 86_400, "year"),
+        (30 * 86_400, "month"),
+        (86_400, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ];
+    let (count, unit) = UNITS
+        .iter()
+        .find(|(unit_secs, _)| secs >= *unit_secs)
+        .map(|(unit_secs, unit)| (secs / unit_secs, *unit))
+        .unwrap_or((secs, "second"));
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural}")
+}
+
+/// Formats `time` as an RFC 3339 timestamp in UTC, e.g. `2024-03-01T12:00:00Z`.
+///
+/// Times before the epoch are clamped to it.
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Civil-from-days conversion, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parses a point in time given as Unix seconds or in UTC as `2024-03-01T12:00:00Z`, the
+/// inverse of [`format_timestamp`]. The trailing `Z` may be left out.
+fn parse_timestamp(input: &str) -> Result<SystemTime> {
+    let input = input.trim();
+    if let Ok(secs) = input.parse::<u64>() {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    let invalid = || {
+        anyhow::anyhow!(
+            "invalid timestamp {input:?}, expected e.g. 2024-03-01T12:00:00Z or Unix seconds"
+        )
+    };
+    let (date, time) = input
+        .strip_suffix('Z')
+        .unwrap_or(input)
+        .split_once('T')
+        .ok_or_else(invalid)?;
+    let fields = |part: &str, separator| {
+        part.split(separator)
+            .map(|field| field.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())
+    };
+    let (date, time) = (fields(date, '-')?, fields(time, ':')?);
+    let (&[year, month, day], &[hour, minute, second]) = (&date[..], &time[..]) else {
+        return Err(invalid());
+    };
+    if !(1970..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+    // Days-from-civil conversion, the inverse of the one in `format_timestamp`.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146_097 + doe)
+        .checked_sub(719_468)
+        .ok_or_else(invalid)?;
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Parses an age such as `90d`: a whole number followed by one of the units
+/// [`format_age`] spells out, abbreviated to `s`, `m`, `h`, `d`, `w`, `mo` or `y`.
+fn parse_age(input: &str) -> Result<Duration> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let invalid = || anyhow::anyhow!("invalid age {input:?}, expected e.g. 90d, 12h or 1y");
+    let count: u64 = number.parse().map_err(|_| invalid())?;
+    let unit_secs = match unit.trim_start().to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        "mo" => 30 * 86_400,
+        "y" => 365 * 86_400,
+        _ => return Err(invalid()),
+    };
+    count
+        .checked_mul(unit_secs)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// Names the kind of `target` when it is neither a directory nor a regular file.
+///
+/// Walking a device or reading from a FIFO either yields nothing useful or blocks forever,
+/// so such targets are rejected up front. Symlinks are judged by what they point to.
+fn unscannable_kind(target: &Path) -> Option<&'static str> {
+    let file_type = std::fs::metadata(target).ok()?.file_type();
+    if file_type.is_dir() || file_type.is_file() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Some("named pipe");
+        } else if file_type.is_socket() {
+            return Some("socket");
+        } else if file_type.is_char_device() {
+            return Some("character device");
+        } else if file_type.is_block_device() {
+            return Some("block device");
+        }
+    }
+    Some("special file")
+}
+
+/// Parses a byte count such as `512`, `64KiB`, `1.5 MiB` or `2G`; the inverse of
+/// [`friendly_bytes`]. Every flag that takes a size goes through here.
+///
+/// Units are case-insensitive. IEC units (`KiB`) and bare prefixes (`K`) are powers of
+/// 1024, SI units (`KB`) powers of 1000. Fractions are rounded down to whole bytes.
+fn parse_size(input: &str) -> Result<u64> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        bail!("invalid size {input:?}: expected a number such as 512 or 1.5MiB");
+    }
+    let parse_digits = |digits: &str| -> Result<u128> {
+        if digits.is_empty() {
+            return Ok(0);
+        }
+        digits
+            .parse()
+            .with_context(|| format!("invalid size {input:?}"))
+    };
+    let whole = parse_digits(whole)?;
+    let fraction_digits = u32::try_from(fraction.len())
+        .ok()
+        .filter(|&len| len <= 18)
+        .with_context(|| format!("too many decimals in {input:?}"))?;
+    let fraction = parse_digits(fraction)?;
+
+    let unit = unit.trim_start().to_ascii_lowercase();
+    let (prefix, base) = match unit.as_str() {
+        "" | "b" => ("", 1024),
+        _ => match (unit.strip_suffix("ib"), unit.strip_suffix('b')) {
+            (Some(prefix), _) => (prefix, 1024),
+            (None, Some(prefix)) => (prefix, 1000),
+            (None, None) => (unit.as_str(), 1024),
+        },
+    };
+    let exponent = match prefix {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        "e" => 6,
+        _ => bail!("unknown size unit {unit:?} in {input:?}, expected e.g. B, KiB, MB or G"),
+    };
+    let multiplier: u128 = u128::pow(base, exponent);
+    let bytes = whole * multiplier + fraction * multiplier / 10u128.pow(fraction_digits);
+    u64::try_from(bytes).with_context(|| format!("size {input:?} is too large"))
+}
+
+/// Runs [`process_entry`] for each of `files` and adds them to `report`, on the threads of
+/// `pool` if there is one. Every worker thread tallies into a report of its own, and these
+/// are merged in file order at the end.
+///
+/// Returns what [`process_entry`] made of each file, in the order of `files`.
+fn process_files<E: ScanEntry + Sync>(
+    files: &[E],
+    report: &mut Report,
+    options: &ScanOptions,
+    pool: Option<&rayon::ThreadPool>,
+) -> Vec<Result<&'static str>> {
+    let Some(pool) = pool else {
+        return files
+            .iter()
+            .map(|file| process_entry(file, report, options))
+            .collect();
+    };
+    let (batch_report, mut outcomes) = pool.install(|| {
+        files
+            .par_iter()
+            .enumerate()
+            .fold(
+                || (Report::default(), Vec::new()),
+                |(mut report, mut outcomes), (index, file)| {
+                    outcomes.push((index, process_entry(file, &mut report, options)));
+                    (report, outcomes)
+                },
+            )
+            .reduce(
+                || (Report::default(), Vec::new()),
+                |(mut report, mut outcomes), (other, other_outcomes)| {
+                    report.merge(other, options.max_errors);
+                    outcomes.extend(other_outcomes);
+                    (report, outcomes)
+                },
+            )
+    });
+    report.merge(batch_report, options.max_errors);
+    if let Some(limit) = options.recent {
+        report.trim_recent(limit);
+    }
+    outcomes.sort_by_key(|(index, _)| *index);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Files handed to each worker thread per batch of a parallel scan. Checks that depend on the
+/// running totals, such as `--limit-bytes` and `--strict`, happen between batches, so a
+/// parallel scan reads at most one batch further than a serial one would.
+const PARALLEL_BATCH_PER_THREAD: usize = 64;
+
+/// Scans every target and merges the results into a single report.
+fn scan_all(targets: &[PathBuf], options: &ScanOptions) -> Result<Report> {
+    let mut report = Report::default();
+    for target in targets {
+        // The byte limit covers all targets together, not each one separately.
+        let options = ScanOptions {
+            limit_bytes: options
+                .limit_bytes
+                .map(|limit| limit.saturating_sub(report.size)),
+            ..options.clone()
+        };
+        report.merge(scan(target.clone(), &options)?, options.max_errors);
+        if report.truncated {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+/// Expands `target` into the roots to scan.
+///
+/// Paths containing glob wildcards (`*`, `?`, `[`) are expanded to the directories they
+/// match, with a leading `~/` standing in for the home directory; it is an error for a
+/// pattern to match no directory at all.
+/// Anything else, including an existing path that merely contains such characters, is taken
+/// literally, exactly as given.
+fn resolve_targets(target: &Path) -> Result<Vec<PathBuf>> {
+    let pattern = target
+        .to_str()
+        .filter(|t| t.contains(['*', '?', '[']) && !target.exists());
+    let Some(pattern) = pattern else {
+        return Ok(vec![target.to_path_buf()]);
+    };
+    let pattern = match (pattern.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest).to_string_lossy().into_owned(),
+        _ => pattern.to_string(),
+    };
+
+    let targets = glob::glob(&pattern)
+        .with_context(|| format!("invalid glob pattern {pattern:?}"))?
+        .filter_ok(|path| path.is_dir())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to expand {pattern:?}"))?;
+    if targets.is_empty() {
+        bail!("no directories match {pattern:?}");
+    }
+    Ok(targets)
+}
+
+fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let result = match (cli.command, cli.scan) {
+        (Some(Command::Scan(args)), _) => {
+            let scan_matches = matches.subcommand_matches("scan").unwrap_or(&matches);
+            run_scan(with_config(*args, scan_matches));
+            Ok(())
+        }
+        (None, Some(args)) => {
+            run_scan(with_config(args, &matches));
+            Ok(())
+        }
+        (Some(Command::Diff(args)), _) => diff::run(&args),
+        (Some(Command::Dedup(args)), _) => dedup::run(&args),
+        (Some(Command::Manifest(args)), _) => manifest::run(&args),
+        (Some(Command::Version(args)), _) => version::run(&args),
+        (None, None) => Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "a target directory or a subcommand is required",
+            )
+            .exit(),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {e:#}");
+        exit(Outcome::Failed);
+    }
+}
+
+/// `args` with the defaults from `sumdir.toml` filled in, where `matches` show that the
+/// flags weren't given, and the output format picked to suit `--output-file`.
+fn with_config(mut args: ScanArgs, matches: &ArgMatches) -> ScanArgs {
+    match config::load() {
+        Ok(config) => {
+            check_oneline(&args, matches).unwrap_or_else(|e| e.exit());
+            config.apply(&mut args, matches);
+            infer_output_format(&mut args, matches);
+            args
+        }
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            exit(Outcome::Failed);
+        }
+    }
+}
+
+/// Rejects `--oneline` together with `--output` on the command line. It can't be a static
+/// `conflicts_with`, as clap would count a format from `SUMDIR_OUTPUT` as a conflict too and
+/// break `--oneline` for everyone who sets the variable.
+fn check_oneline(args: &ScanArgs, matches: &ArgMatches) -> Result<(), clap::Error> {
+    if args.oneline && matches.value_source("output") == Some(ValueSource::CommandLine) {
+        return Err(Cli::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "the argument '--oneline' cannot be used with '--output <OUTPUT>'",
+        ));
+    }
+    Ok(())
+}
+
+/// Picks the output format from the extension of `--output-file`, unless `--output` or
+/// `SUMDIR_OUTPUT` asks for one. The file name beats a format from `sumdir.toml`; an
+/// extension that says nothing about the format leaves the format alone.
+fn infer_output_format(args: &mut ScanArgs, matches: &ArgMatches) {
+    let explicit = matches!(
+        matches.value_source("output"),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    );
+    if !explicit && let Some(format) = args.output_file.as_deref().and_then(OutputFormat::for_path)
+    {
+        args.output = format;
+    }
+}
+
+/// The `scan` subcommand: summarize the target and print the report.
+fn run_scan(cli: ScanArgs) {
+    if cli.flatten && !matches!(cli.output, OutputFormat::Json) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--flatten can only be used with --output json",
+            )
+            .exit();
+    }
+    if cli.sort_keys && !matches!(cli.output, OutputFormat::Json) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--sort-keys can only be used with --output json",
+            )
+            .exit();
+    }
+    if !cli.mime
+        && cli
+            .csv_columns
+            .as_ref()
+            .is_some_and(|columns| columns.contains(&CsvColumn::Bytes))
+    {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--csv-columns bytes needs --mime, sizes are only tracked per mimetype",
+            )
+            .exit();
+    }
+    let targets = match cli.target.as_deref().map(resolve_targets) {
+        None => Ok(Vec::new()),
+        Some(targets) => targets,
+    };
+    let targets = match targets {
+        Ok(targets) => targets,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            exit(Outcome::Failed);
+        }
+    };
+    if cli.by_owner && cfg!(not(unix)) {
+        eprintln!("error: --by-owner is only supported on Unix, where files have an owning uid");
+        exit(Outcome::Failed);
+    }
+    if cli.resume_file.is_some() && targets.len() > 1 {
+        eprintln!("error: --resume-file can only be used with a single target");
+        exit(Outcome::Failed);
+    }
+    for target in &targets {
+        if !target.exists() {
+            eprintln!(
+                "{} does not exist",
+                target.to_str().unwrap_or("<invalid path>")
+            );
+            exit(Outcome::Failed);
+        }
+        if let Some(kind) = unscannable_kind(target) {
+            eprintln!(
+                "{}: target is a {kind}, expected a file or directory",
+                target.to_str().unwrap_or("<invalid path>")
+            );
+            exit(Outcome::Failed);
+        }
+    }
+    let histogram_scheme = cli.size_histogram.then(|| HistogramScheme {
+        base: cli.histogram_base,
+        buckets: cli
+            .histogram_buckets
+            .map(|buckets| usize::try_from(buckets).unwrap_or(usize::MAX)),
+    });
+    let options = ScanOptions {
+        progress_bar: cli.progress_bar,
+        max_errors: cli.max_errors,
+        normalize_unicode: cli.normalize_unicode,
+        strict: cli.strict,
+        // The one-line summary has no use for mimetypes, so don't pay for sniffing.
+        extensions_only: cli.extensions_only || cli.oneline,
+        mimetypes_only: cli.mimetypes_only,
+        include_root: cli.include_root,
+        follow_symlinks: cli.follow_symlinks,
+        exclude_dirs: cli.exclude_dir,
+        exclude_hidden_dirs: cli.exclude_hidden_dirs,
+        filter: None,
+        ext_whitelist: cli.ext_whitelist_from,
+        explain: cli.explain,
+        count_symlinks_as_files: cli.count_symlinks_as_files,
+        mmap: cli.mmap,
+        sniff_bytes: cli
+            .sniff_bytes
+            .map(|size| usize::try_from(size).unwrap_or(usize::MAX)),
+        sniff_max_bytes: cli
+            .sniff_max_bytes
+            .map(|size| usize::try_from(size).unwrap_or(usize::MAX)),
+        mime_from_ext: cli.mime_from_ext,
+        no_mime_fallback: cli.no_mime_fallback,
+        collapse_mime_subtypes: cli.collapse_mime_subtypes,
+        limit_bytes: cli.limit_bytes,
+        warn_slow_files: cli.warn_slow_files.map(Duration::from_millis),
+        check_integrity: cli.check_integrity,
+        suggest_extensions: cli.fix_extensions,
+        heartbeat: cli.heartbeat.map(Duration::from_secs),
+        merge_extensionless_by_magic: cli.merge_extensionless_by_magic,
+        split_empty_extensions: cli.report_empty_extensions_separately,
+        group_regex: cli
+            .group_regex
+            .zip(cli.group_replace)
+            .map(|(pattern, replacement)| GroupRegex {
+                pattern,
+                replacement,
+            }),
+        resume_file: cli.resume_file,
+        include_files: cli.include_files.then_some(cli.include_files_limit),
+        recent: cli.recent,
+        cold_before: cli.cold_threshold.map(|age| {
+            let now = cli.now.unwrap_or_else(SystemTime::now);
+            now.checked_sub(age).unwrap_or(UNIX_EPOCH)
+        }),
+        cold_by: cli.cold_by,
+        threads: cli.threads,
+        case_collisions: cli.case_collisions,
+        dir_entry_counts: cli.largest_dirs.is_some(),
+        dir_file_counts: cli.fat_dirs.is_some(),
+        top_level_sizes: cli.by_dir,
+        owner_sizes: cli.by_owner,
+        name_counts: cli.top_names.is_some(),
+        depth_counts: cli.sparkline.is_some(),
+        rollup_depth: cli.rollup_depth,
+        histogram: histogram_scheme,
+    };
+    if cli.benchmark {
+        if let Err(e) = benchmark::run(&targets, &options, &mut io::stdout().lock()) {
+            eprintln!("error: {e:#}");
+            exit(Outcome::Failed);
+        }
+        return;
+    }
+    let report = match &cli.import {
+        Some(path) => export::load(path),
+        None => scan_all(&targets, &options),
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            exit(Outcome::Failed);
+        }
+    };
+    if let Some(path) = &cli.export
+        && let Err(e) = export::save(path, &report)
+    {
+        eprintln!("error: {e:#}");
+        exit(Outcome::Failed);
+    }
+    let display_options = DisplayOptions {
+        targets: if cli.no_header {
+            Vec::new()
+        } else {
+            targets.clone()
+        },
+        format: cli.output,
+        use_mime: cli.mime || cli.mimetypes_only,
+        both: cli.both,
+        flatten: cli.flatten,
+        sort_keys: cli.sort_keys,
+        fat_dirs: cli.fat_dirs,
+        largest_dirs: cli.largest_dirs,
+        top_names: cli.top_names,
+        sparkline: cli.sparkline,
+        plain_sparklines: !locale_is_utf8(),
+        by_dir: cli.by_dir,
+        histogram: histogram_scheme,
+        rollup_depth: cli.rollup_depth,
+        by_owner: cli.by_owner,
+        case_collisions: cli.case_collisions,
+        top: cli.top,
+        other_label: cli.other_label,
+        list_errors: cli.list_errors,
+        dedupe_errors: cli.dedupe_report,
+        include_files: cli.include_files,
+        recent: cli.recent,
+        cold_data: cli.cold_threshold.is_some(),
+        precision: cli.precision,
+        time_format: cli.time_format,
+        now: cli.now,
+        slow_files: cli.warn_slow_files.is_some(),
+        suspect_files: cli.check_integrity,
+        relative_to: cli.relative_to,
+        strip_prefix_components: cli.strip_prefix_components,
+        oneline: cli.oneline,
+        no_summary: cli.no_summary,
+        no_csv_header: cli.no_header || cli.no_csv_header,
+        csv_columns: cli.csv_columns,
+        bars: cli.bars.then(|| {
+            terminal_size::terminal_size()
+                .map_or(DEFAULT_LINE_WIDTH, |(width, _)| usize::from(width.0))
+        }),
+        print0_errors: cli.print0_errors,
+        scan_options: Some(options.clone()),
+    };
+    let written = match &cli.output_file {
+        Some(path) => write_output_file(&report, path, cli.gzip, &display_options),
+        None => report
+            .display(&mut io::stdout().lock(), &display_options)
+            .context("failed to write report"),
+    };
+    if let Err(e) = written {
+        eprintln!("error: {e:#}");
+        exit(Outcome::Failed);
+    }
+    let extra_outputs = [
+        (cli.also_json, OutputFormat::Json),
+        (cli.also_csv, OutputFormat::Csv),
+    ];
+    for (path, format) in extra_outputs {
+        if let Some(path) = path
+            && let Err(e) = write_report_file(&report, &path, format, &display_options)
+        {
+            eprintln!("error: {e:#}");
+            exit(Outcome::Failed);
+        }
+    }
+    if cli.suggest_aliases {
+        for (mimetype, exts) in report.extension_aliases() {
+            let Some((last, rest)) = exts.split_last() else {
+                continue;
+            };
+            let quantifier = if rest.len() == 1 { "both" } else { "all" };
+            eprintln!(
+                "hint: {} and {last} {quantifier} resolve to {mimetype}, consider merging them",
+                rest.join(", ")
+            );
+        }
+    }
+    if cli.fix_extensions && !fix_extensions(&report.misnamed_files, cli.dry_run) {
+        exit(Outcome::Failed);
+    }
+    let violations: Vec<String> = cli
+        .warn_if
+        .iter()
+        .filter_map(|threshold| threshold.violation(&report))
+        .collect();
+    for violation in &violations {
+        eprintln!("warning: {violation}");
+    }
+    let mut drifted = false;
+    if let Some(path) = &cli.compare_to {
+        let baseline = match export::load(path) {
+            Ok(baseline) => baseline,
+            Err(e) => {
+                eprintln!("error: {e:#}");
+                exit(Outcome::Failed);
+            }
+        };
+        let drift = diff::drift(&baseline, &report, display_options.use_mime, cli.tolerance);
+        if !drift.is_empty() {
+            eprintln!(
+                "warning: drifted from {:?} by more than {}%:",
+                path, cli.tolerance
+            );
+            for line in &drift {
+                eprintln!("  {line}");
+            }
+            drifted = true;
+        }
+    }
+    // Exit codes are for one-shot scans; a watch keeps going whatever the first scan found,
+    // with the warnings above as the only trace of it.
+    if cli.watch {
+        if let Err(e) = watch::run(&targets, &options, &display_options) {
+            eprintln!("error: {e:#}");
+            exit(Outcome::Failed);
+        }
+        return;
+    }
+    let outcome = Outcome::of_scan(&report, !violations.is_empty() || drifted);
+    let outcome = if cli.detailed_exit_codes {
+        outcome
+    } else {
+        outcome.coarse()
+    };
+    if outcome != Outcome::Clean {
+        exit(outcome);
+    }
+}
+
+/// Renames every misnamed file to the extension its content suggests, or with `dry_run`
+/// prints the `mv` commands that would, and sums up on stderr. A file is never renamed over
+/// an existing one.
+///
+/// Returns whether every rename went through.
+fn fix_extensions(files: &[MisnamedFile], dry_run: bool) -> bool {
+    let mut renamed = 0;
+    for file in files {
+        let new_path = file.path.with_extension(&file.suggested_ext);
+        if dry_run {
+            eprintln!(
+                "mv -n -- {} {}",
+                shell_quote(&file.path),
+                shell_quote(&new_path)
+            );
+        } else if new_path.exists() {
+            eprintln!(
+                "error: not renaming {:?}, {:?} already exists",
+                file.path, new_path
+            );
+        } else if let Err(e) = std::fs::rename(&file.path, &new_path) {
+            eprintln!("error: failed to rename {:?}: {e}", file.path);
+        } else {
+            renamed += 1;
+        }
+    }
+    if dry_run {
+        eprintln!("{} would be renamed", counted(files.len(), "misnamed file"));
+        return true;
+    }
+    eprintln!(
+        "renamed {} of {}",
+        renamed,
+        counted(files.len(), "misnamed file")
+    );
+    renamed == files.len()
+}
+
+/// `path` in single quotes, so a POSIX shell takes it literally.
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+/// Held by tests that set `SUMDIR_OUTPUT` and by those whose parsed arguments depend on it,
+/// as tests run in parallel.
+#[cfg(test)]
+static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// A fresh, uniquely named directory for one test, deleted again when dropped.
+#[cfg(test)]
+fn test_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("sumdir_test")
+        .tempdir()
+        .expect("failed to create test dir")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_testdata_folder() {
+        let report = scan("testdata".into(), &ScanOptions::default()).expect("scan failed");
+        let num_files: i32 = report.extensions.values().sum();
+        assert_eq!(num_files, 27);
+        assert_eq!(report.folders.len(), 5);
+        assert_eq!(report.errors.len(), 0);
+        // Verify some expected extensions
+        assert_eq!(report.extensions.get("png"), Some(&2)); // sample.png + masquerading.png
+        assert_eq!(report.extensions.get("pdf"), Some(&1));
+        assert_eq!(report.extensions.get("jpg"), Some(&1));
+        assert_eq!(report.extensions.get("docx"), Some(&1));
+    }
+
+    #[test]
+    fn test_detect_mimetype_png() {
+        use std::io::Write;
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let file_path = dir.join("test.png");
+        // PNG magic bytes: 89 50 4E 47 0D 0A 1A 0A
+        let png_header: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        let mut file = File::create(&file_path).expect("failed to create test file");
+        file.write_all(&png_header)
+            .expect("failed to write test file");
+
+        assert_eq!(
+            detect_mimetype(&file_path, &ScanOptions::default())
+                .expect("failed to detect mimetype")
+                .0,
+            "image/png"
+        );
+    }
+
+    #[test]
+    fn test_detect_mimetype_pdf() {
+        use std::io::Write;
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let file_path = dir.join("test.pdf");
+        // PDF magic bytes: %PDF
+        let mut file = File::create(&file_path).expect("failed to create test file");
+        file.write_all(b"%PDF-1.4")
+            .expect("failed to write test file");
+
+        assert_eq!(
+            detect_mimetype(&file_path, &ScanOptions::default())
+                .expect("failed to detect mimetype")
+                .0,
+            "application/pdf"
+        );
+    }
+
+    #[test]
+    fn test_detect_mimetype_unknown() {
+        use std::io::Write;
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let file_path = dir.join("test.txt");
+        let mut file = File::create(&file_path).expect("failed to create test file");
+        file.write_all(b"Hello, world!")
+            .expect("failed to write test file");
+
+        assert_eq!(
+            detect_mimetype(&file_path, &ScanOptions::default())
+                .expect("failed to detect mimetype")
+                .0,
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn test_scan_populates_mimetypes() {
+        use std::io::Write;
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+
+        // Create a PNG file
+        let png_path = dir.join("image.png");
+        let png_header: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        File::create(&png_path)
+            .expect("failed to create png")
+            .write_all(&png_header)
+            .expect("failed to write png");
+
+        // Create a PDF file
+        let pdf_path = dir.join("doc.pdf");
+        File::create(&pdf_path)
+            .expect("failed to create pdf")
+            .write_all(b"%PDF-1.4")
+            .expect("failed to write pdf");
+
+        // Create a text file (unknown to infer)
+        let txt_path = dir.join("readme.txt");
+        File::create(&txt_path)
+            .expect("failed to create txt")
+            .write_all(b"Hello")
+            .expect("failed to write txt");
+
+        let report = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(report.mimetypes.get("image/png"), Some(&1));
+        assert_eq!(report.mimetypes.get("application/pdf"), Some(&1));
+        assert_eq!(report.mimetypes.get("application/octet-stream"), Some(&1));
+        assert_eq!(report.mimetypes.len(), 3);
+        assert_eq!(report.errors.len(), 0);
+    }
+
+    #[test]
+    fn test_testdata_mimetypes() {
+        let report = scan("testdata".into(), &ScanOptions::default()).expect("scan failed");
+        // Verify various MIME types are detected correctly
+        assert_eq!(report.mimetypes.get("image/png"), Some(&1));
+        assert_eq!(report.mimetypes.get("image/jpeg"), Some(&1));
+        assert_eq!(report.mimetypes.get("image/gif"), Some(&1));
+        assert_eq!(report.mimetypes.get("application/pdf"), Some(&1));
+        assert_eq!(report.mimetypes.get("audio/mpeg"), Some(&1));
+        // DOCX/XLSX/PPTX detected as zip, DOC/XLS as ole-storage
+        assert_eq!(report.mimetypes.get("application/zip"), Some(&4));
+        assert_eq!(report.mimetypes.get("application/x-ole-storage"), Some(&2));
+        // Text files without magic bytes fall back to octet-stream
+        assert!(
+            report
+                .mimetypes
+                .get("application/octet-stream")
+                .expect("expected octet-stream entries")
+                >= &1
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_scan_unreadable_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+
+        // Create a subdirectory with no read permissions
+        let unreadable_dir = dir.join("no_access");
+        std::fs::create_dir_all(&unreadable_dir).expect("failed to create unreadable dir");
+
+        // Create a file inside the unreadable directory first
+        let hidden_file = unreadable_dir.join("hidden.txt");
+        std::fs::write(&hidden_file, "secret").expect("failed to write hidden file");
+
+        // Remove read permissions from the directory
+        std::fs::set_permissions(&unreadable_dir, std::fs::Permissions::from_mode(0o000))
+            .expect("failed to set permissions");
+
+        // Create a readable file
+        let readable_file = dir.join("readable.txt");
+        std::fs::write(&readable_file, "hello").expect("failed to write readable file");
+
+        let report = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+
+        // Should have scanned the readable file
+        assert_eq!(report.extensions.get("txt"), Some(&1));
+        // Should have recorded errors for the unreadable directory
+        assert!(
+            !report.errors.is_empty(),
+            "expected errors for unreadable directory"
+        );
+        // Error should contain the path
+        assert!(
+            report
+                .errors
+                .iter()
+                .any(|e| e.path.to_string_lossy().contains("no_access")),
+            "error should contain path to unreadable directory"
+        );
+
+        // Restore permissions for cleanup
+        std::fs::set_permissions(&unreadable_dir, std::fs::Permissions::from_mode(0o755))
+            .expect("failed to restore permissions");
+    }
+
+    #[test]
+    fn test_scan_error_contains_path_and_message() {
+        // Test that ScanError properly stores both path and message
+        let error = ScanError {
+            path: PathBuf::from("/some/path/file.txt"),
+            message: "test error message".to_string(),
+            ..ScanError::default()
+        };
+
+        assert_eq!(error.path, PathBuf::from("/some/path/file.txt"));
+        assert_eq!(error.message, "test error message");
+    }
+
+    #[test]
+    fn test_report_with_errors_displays_count() {
+        let mut report = Report::default();
+        report.errors.push(ScanError {
+            path: PathBuf::from("/path/to/file1.txt"),
+            message: "error 1".to_string(),
+            ..ScanError::default()
+        });
+        report.errors.push(ScanError {
+            path: PathBuf::from("/path/to/file2.txt"),
+            message: "error 2".to_string(),
+            ..ScanError::default()
+        });
+
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.errors[0].path, PathBuf::from("/path/to/file1.txt"));
+        assert_eq!(report.errors[1].path, PathBuf::from("/path/to/file2.txt"));
+    }
+
+    #[test]
+    fn test_display_skips_empty_groups() {
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 2);
+        report.extensions.insert("txt".to_string(), 0);
+
+        for format in [OutputFormat::Text, OutputFormat::Csv, OutputFormat::Json] {
+            let mut out = Vec::new();
+            let options = DisplayOptions {
+                format,
+                ..DisplayOptions::default()
+            };
+            report
+                .display(&mut out, &options)
+                .expect("failed to display report");
+            let output = String::from_utf8(out).expect("output is not UTF-8");
+            assert!(output.contains("png"));
+            assert!(!output.contains("txt"), "txt row leaked into: {output}");
+        }
+    }
+
+    #[test]
+    fn test_max_errors_caps_recorded_errors() {
+        let mut report = Report::default();
+        for i in 0..5 {
+            report.push_error(
+                ScanError {
+                    path: PathBuf::from(format!("/path/{i}")),
+                    message: "error".to_string(),
+                    ..ScanError::default()
+                },
+                Some(2),
+            );
+        }
+
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.suppressed_errors, 3);
+        assert_eq!(report.error_count(), 5);
+    }
+
+    #[test]
+    fn test_normalize_unicode_merges_extensions() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        // "café" precomposed (NFC) and with a combining acute accent (NFD)
+        std::fs::write(dir.join("a.caf\u{e9}"), "nfc").expect("failed to write nfc file");
+        std::fs::write(dir.join("b.cafe\u{301}"), "nfd").expect("failed to write nfd file");
+
+        let split = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+        let merged = scan(
+            dir.clone(),
+            &ScanOptions {
+                normalize_unicode: true,
+                ..ScanOptions::default()
+            },
+        )
+        .expect("scan failed");
+
+        assert_eq!(split.extensions.len(), 2);
+        assert_eq!(merged.extensions.len(), 1);
+        assert_eq!(merged.extensions.get("caf\u{e9}"), Some(&2));
+    }
+
+    #[test]
+    fn test_display_json_flat_escapes_keys() {
+        let mut report = Report {
+            size: 42,
+            ..Report::default()
+        };
+        report.extensions.insert("png".to_string(), 2);
+        report.extensions.insert("tar.gz".to_string(), 1);
+        report.mimetypes.insert("image/png".to_string(), 2);
+        report.mimetype_sizes.insert("image/png".to_string(), 42);
+
+        let mut out = Vec::new();
+        let options = DisplayOptions {
+            format: OutputFormat::Json,
+            flatten: true,
+            ..DisplayOptions::default()
+        };
+        report
+            .display(&mut out, &options)
+            .expect("failed to display report");
+        let output = String::from_utf8(out).expect("output is not UTF-8");
+
+        assert_eq!(
+            output.trim_end(),
+            r#"{"files":3,"folders":0,"size":42,"errors":0,"truncated":false,"ext.png":2,"ext.tar\\.gz":1,"mime.image/png":2,"mime_size.image/png":42}"#
+        );
+    }
+
+    #[test]
+    fn test_display_json_sort_keys_orders_groups_alphabetically() {
+        let mut report = Report::default();
+        report.extensions.insert("zip".to_string(), 1);
+        report.extensions.insert("png".to_string(), 5);
+        report.extensions.insert("gif".to_string(), 3);
+        report.extensions.insert("bmp".to_string(), 1);
+        let options = DisplayOptions {
+            format: OutputFormat::Json,
+            sort_keys: true,
+            top: Some(3),
+            ..DisplayOptions::default()
+        };
+
+        let mut out = Vec::new();
+        report
+            .display(&mut out, &options)
+            .expect("failed to display report");
+        let output = String::from_utf8(out).expect("output is not UTF-8");
+
+        let position = |key: &str| {
+            output
+                .find(&format!("\"{key}\":"))
+                .unwrap_or_else(|| panic!("{key} missing from output"))
+        };
+        assert!(position("bmp") < position("gif"));
+        assert!(position("gif") < position("png"));
+        // The rest stays at the end, whatever its label
+        assert!(position("png") < position(DEFAULT_OTHER_LABEL));
+        assert!(!output.contains("\"zip\":"));
+    }
+
+    #[test]
+    fn test_display_json_echoes_scan_options() {
+        let report = Report::default();
+        let options = DisplayOptions {
+            format: OutputFormat::Json,
+            scan_options: Some(ScanOptions {
+                follow_symlinks: true,
+                exclude_dirs: vec!["node_modules".into()],
+                ..ScanOptions::default()
+            }),
+            ..DisplayOptions::default()
+        };
+
+        let mut out = Vec::new();
+        report
+            .display(&mut out, &options)
+            .expect("failed to display report");
+        let json: serde_json::Value = serde_json::from_slice(&out).expect("invalid JSON");
+
+        assert_eq!(json["options"]["follow_symlinks"], true);
+        assert_eq!(
+            json["options"]["exclude_dir"],
+            serde_json::json!(["node_modules"])
+        );
+        assert_eq!(json["options"]["limit_bytes"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_json_escape() {
+        assert_eq!(json_escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(json_escape("line\nbreak"), r"line\nbreak");
+        assert_eq!(json_escape("\u{1}"), r"\u0001");
+    }
+
+    #[test]
+    fn test_display_prometheus() {
+        let mut report = Report {
+            size: 42,
+            ..Report::default()
+        };
+        report.extensions.insert("png".to_string(), 2);
+        report.extensions.insert("we\"ird".to_string(), 1);
+
+        let mut out = Vec::new();
+        let options = DisplayOptions {
+            format: OutputFormat::Prometheus,
+            ..DisplayOptions::default()
+        };
+        report
+            .display(&mut out, &options)
+            .expect("failed to display report");
+        let output = String::from_utf8(out).expect("output is not UTF-8");
+
+        assert!(output.contains("# TYPE sumdir_files_total gauge\nsumdir_files_total 3\n"));
+        assert!(output.contains("sumdir_bytes_total 42\n"));
+        assert!(output.contains("sumdir_files{extension=\"png\"} 2\n"));
+        assert!(output.contains(r#"sumdir_files{extension="we\"ird"} 1"#));
+    }
+
+    #[test]
+    fn test_mimetype_sizes_sum_to_total() {
+        let report = scan("testdata".into(), &ScanOptions::default()).expect("scan failed");
+
+        let mime_total: u64 = report.mimetype_sizes.values().sum();
+        assert_eq!(mime_total, report.size);
+        assert_eq!(report.mimetype_sizes.len(), report.mimetypes.len());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_strict_aborts_on_first_error() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join("ok.txt"), "fine").expect("failed to write file");
+        // A dangling symlink can't be stat'ed, which makes process_entry fail
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("dangling.txt"))
+            .expect("failed to create symlink");
+
+        let lenient = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+        let strict = scan(
+            dir.clone(),
+            &ScanOptions {
+                strict: true,
+                ..ScanOptions::default()
+            },
+        );
+
+        assert_eq!(lenient.errors.len(), 1);
+        let error = strict.expect_err("strict scan should fail");
+        assert!(error.to_string().contains("dangling.txt"));
+    }
+
+    #[test]
+    fn test_fat_dirs_counts_immediate_files() {
+        let options = ScanOptions {
+            dir_file_counts: true,
+            ..ScanOptions::default()
+        };
+        let report = scan("testdata".into(), &options).expect("scan failed");
+
+        let fat_dirs = report.fat_dirs(2);
+
+        assert_eq!(fat_dirs.len(), 2);
+        assert_eq!(fat_dirs[0].0, &PathBuf::from("testdata/documents"));
+        assert_eq!(fat_dirs[0].1, 8);
+        assert_eq!(fat_dirs[1].0, &PathBuf::from("testdata/images"));
+        assert_eq!(fat_dirs[1].1, 7);
+        assert_eq!(
+            report.dir_file_counts.get(&PathBuf::from("testdata")),
+            Some(&3)
+        );
+
+        let report = scan("testdata".into(), &ScanOptions::default()).expect("scan failed");
+        assert!(report.dir_file_counts.is_empty());
+    }
+
+    #[test]
+    fn test_extensions_only_skips_mimetypes() {
+        let options = ScanOptions {
+            extensions_only: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan("testdata".into(), &options).expect("scan failed");
+
+        assert_eq!(report.extensions.values().sum::<i32>(), 27);
+        assert!(report.mimetypes.is_empty());
+        assert!(report.mimetype_sizes.is_empty());
+    }
+
+    #[test]
+    fn test_mimetypes_only_skips_extensions() {
+        let options = ScanOptions {
+            mimetypes_only: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan("testdata".into(), &options).expect("scan failed");
+
+        assert!(report.extensions.is_empty());
+        assert_eq!(report.mimetypes.values().sum::<i32>(), 27);
+        assert_eq!(report.file_count(), 27);
+    }
+
+    #[test]
+    fn test_resolve_targets_literal_path_is_untouched() {
+        let targets = resolve_targets(Path::new("testdata")).expect("failed to resolve");
+
+        assert_eq!(targets, vec![PathBuf::from("testdata")]);
+    }
+
+    #[test]
+    fn test_resolve_targets_existing_path_with_wildcards_is_literal() {
+        let temp = test_dir();
+        let dir = temp.path().join("a[1]");
+        std::fs::create_dir(&dir).expect("failed to create dir");
+
+        let targets = resolve_targets(&dir).expect("failed to resolve");
+
+        assert_eq!(targets, vec![dir]);
+    }
+
+    #[test]
+    fn test_resolve_targets_expands_globs() {
+        let targets = resolve_targets(Path::new("testdata/[ai]*")).expect("failed to resolve");
+
+        assert_eq!(
+            targets,
+            vec![
+                PathBuf::from("testdata/archives"),
+                PathBuf::from("testdata/audio"),
+                PathBuf::from("testdata/images"),
+            ]
+        );
+        assert!(resolve_targets(Path::new("testdata/nothing-*")).is_err());
+    }
+
+    #[test]
+    fn test_scan_all_merges_reports() {
+        let targets = resolve_targets(Path::new("testdata/[ai]*")).expect("failed to resolve");
+
+        let report = scan_all(&targets, &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(report.extensions.values().sum::<i32>(), 15);
+        assert_eq!(report.extensions.get("png"), Some(&1));
+        assert_eq!(report.extensions.get("zip"), Some(&1));
+    }
+
+    #[test]
+    fn test_include_root_counts_root_folder() {
+        let options = ScanOptions {
+            include_root: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan("testdata".into(), &options).expect("scan failed");
+
+        assert_eq!(report.folders.len(), 6);
+        assert_eq!(report.folders[0], PathBuf::from("testdata"));
+        assert_eq!(report.extensions.values().sum::<i32>(), 27);
+    }
+
+    #[test]
+    fn test_scan_single_file_target() {
+        let report =
+            scan("testdata/plain.txt".into(), &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(report.file_count(), 1);
+        assert!(report.folders.is_empty());
+        assert_eq!(report.extensions.get("txt"), Some(&1));
+        assert_eq!(report.size, 12);
+    }
+
+    /// The report of a scan of the single file `testdata/plain.txt`, in `format`.
+    fn display_single_file(format: OutputFormat) -> String {
+        let report =
+            scan("testdata/plain.txt".into(), &ScanOptions::default()).expect("scan failed");
+        let options = DisplayOptions {
+            format,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+        report
+            .display(&mut out, &options)
+            .expect("failed to display report");
+        String::from_utf8(out).expect("output is not UTF-8")
+    }
+
+    #[test]
+    fn test_display_text_of_single_file() {
+        let output = display_single_file(OutputFormat::Text);
+
+        assert!(output.starts_with("1 file, 0 folders, 12 B\n"));
+        assert!(output.contains("\ntxt: 1\n"));
+    }
+
+    #[test]
+    fn test_both_shows_extensions_and_mimetypes() {
+        let report =
+            scan("testdata/plain.txt".into(), &ScanOptions::default()).expect("scan failed");
+        let display = |format| {
+            let options = DisplayOptions {
+                format,
+                both: true,
+                ..DisplayOptions::default()
+            };
+            let mut out = Vec::new();
+            report
+                .display(&mut out, &options)
+                .expect("failed to display report");
+            String::from_utf8(out).expect("output is not UTF-8")
+        };
+
+        let text = display(OutputFormat::Text);
+        let csv = display(OutputFormat::Csv);
+        let json: serde_json::Value =
+            serde_json::from_str(&display(OutputFormat::Json)).expect("invalid JSON");
+
+        assert!(
+            text.contains(
+                "Extensions:\ntxt: 1\n\nMimetypes:\napplication/octet-stream: 1 (12 B)\n"
+            )
+        );
+        assert_eq!(
+            csv,
+            "extension,count\ntxt,1\n\nmimetype,count,size\napplication/octet-stream,1,12\n"
+        );
+        assert_eq!(json["extensions"]["txt"], 1);
+        assert_eq!(json["mimetypes"]["application/octet-stream"], 1);
+        assert_eq!(json["mimetype_sizes"]["application/octet-stream"], 12);
+    }
+
+    #[test]
+    fn test_display_csv_of_single_file() {
+        assert_eq!(
+            display_single_file(OutputFormat::Csv),
+            "extension,count\ntxt,1\n"
+        );
+    }
+
+    #[test]
+    fn test_display_json_of_single_file() {
+        let output = display_single_file(OutputFormat::Json);
+        let json: serde_json::Value = serde_json::from_str(&output).expect("invalid JSON");
+
+        assert_eq!(json["files"], 1);
+        assert_eq!(json["folders"], 0);
+        assert_eq!(json["extensions"], serde_json::json!({ "txt": 1 }));
+    }
+
+    #[test]
+    fn test_display_html_of_single_file() {
+        let output = display_single_file(OutputFormat::Html);
+
+        assert!(output.contains("<p>1 file, 0 folders, 12 B</p>"));
+        assert!(output.contains("<td>txt</td>"));
+    }
+
+    #[test]
+    fn test_detect_mimetype_mmap() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let png_path = dir.join("test.png");
+        let png_header: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        std::fs::write(&png_path, png_header).expect("failed to write test file");
+        let empty_path = dir.join("empty");
+        std::fs::write(&empty_path, "").expect("failed to write empty file");
+        let options = ScanOptions {
+            mmap: true,
+            ..ScanOptions::default()
+        };
+
+        assert_eq!(
+            detect_mimetype(&png_path, &options)
+                .expect("failed to detect mimetype")
+                .0,
+            "image/png"
+        );
+        // Empty files can't be mapped and go through the read fallback
+        assert_eq!(
+            detect_mimetype(&empty_path, &options)
+                .expect("failed to detect mimetype")
+                .0,
+            "inode/x-empty"
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").expect("failed to parse"), 512);
+        assert_eq!(parse_size("64KiB").expect("failed to parse"), 64 * 1024);
+        assert_eq!(parse_size("10GiB").expect("failed to parse"), 10 << 30);
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn test_parse_size_units() {
+        let cases = [
+            ("1024", 1024),
+            ("0", 0),
+            ("12B", 12),
+            ("1KiB", 1024),
+            ("1kib", 1024),
+            ("1K", 1024),
+            ("1k", 1024),
+            ("1KB", 1000),
+            ("1kB", 1000),
+            ("2G", 2 << 30),
+            ("2GB", 2_000_000_000),
+            ("3TiB", 3 << 40),
+            ("1PB", 1_000_000_000_000_000),
+            ("1.5MiB", 1_572_864),
+            ("1.5M", 1_572_864),
+            ("0.5KB", 500),
+            (".5K", 512),
+            ("2.", 2),
+            ("1.0001KiB", 1024),
+            (" 64 KiB ", 64 * 1024),
+            ("15EiB", 15 << 60),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_size(input).unwrap_or_else(|e| panic!("{input:?}: {e}")),
+                expected,
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_size_rejects_invalid_input() {
+        for input in [
+            "", " ", "KiB", ".", "1..5K", "-1", "1.5.5M", "1 2K", "1XB", "1KiBB",
+        ] {
+            assert!(parse_size(input).is_err(), "{input:?} should be rejected");
+        }
+        let error = parse_size("17EiB").expect_err("17EiB overflows u64");
+        assert!(error.to_string().contains("too large"));
+    }
+
+    #[test]
+    fn test_limit_bytes_truncates_scan() {
+        let options = ScanOptions {
+            limit_bytes: Some(100),
+            ..ScanOptions::default()
+        };
+
+        let full = scan("testdata".into(), &ScanOptions::default()).expect("scan failed");
+        let limited = scan("testdata".into(), &options).expect("scan failed");
+
+        assert!(!full.truncated);
+        assert!(limited.truncated);
+        assert!(limited.size >= 100);
+        assert!(limited.size < full.size);
+    }
+
+    #[test]
+    fn test_write_report_file() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let csv_path = dir.join("report.csv");
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 2);
+
+        write_report_file(
+            &report,
+            &csv_path,
+            OutputFormat::Csv,
+            &DisplayOptions::default(),
+        )
+        .expect("failed to write report");
+
+        let written = std::fs::read_to_string(&csv_path).expect("failed to read report");
+        assert_eq!(written, "extension,count\npng,2\n");
+    }
+
+    #[test]
+    fn test_output_format_follows_output_file() {
+        let scan_args = |argv: &[&str]| {
+            let matches = Cli::command()
+                .try_get_matches_from(argv)
+                .expect("failed to parse arguments");
+            let mut args = Cli::from_arg_matches(&matches)
+                .expect("failed to parse arguments")
+                .scan
+                .expect("expected scan arguments");
+            infer_output_format(&mut args, &matches);
+            args.output
+        };
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let inferred = scan_args(&["sumdir", "dir", "--output-file", "report.json"]);
+        let compressed = scan_args(&["sumdir", "dir", "--output-file", "r.csv.gz", "--gzip"]);
+        let explicit = scan_args(&["sumdir", "dir", "--output-file", "report.json", "-o", "csv"]);
+        let unknown = scan_args(&["sumdir", "dir", "--output-file", "report.out"]);
+
+        assert!(matches!(inferred, OutputFormat::Json));
+        assert!(matches!(compressed, OutputFormat::Csv));
+        assert!(matches!(explicit, OutputFormat::Csv));
+        assert!(matches!(unknown, OutputFormat::Text));
+    }
+
+    #[test]
+    fn test_write_output_file_gzip() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 2);
+        let options = DisplayOptions {
+            format: OutputFormat::Csv,
+            ..DisplayOptions::default()
+        };
+
+        write_output_file(&report, &dir.join("report.csv"), true, &options)
+            .expect("failed to write report");
+        write_output_file(&report, &dir.join("again.csv.gz"), true, &options)
+            .expect("failed to write report");
+
+        let compressed = File::open(dir.join("report.csv.gz")).expect("failed to open report");
+        let mut written = String::new();
+        flate2::read::GzDecoder::new(compressed)
+            .read_to_string(&mut written)
+            .expect("failed to decompress report");
+        assert_eq!(written, "extension,count\npng,2\n");
+        assert!(dir.join("again.csv.gz").exists());
+        assert!(!dir.join("report.csv").exists());
+    }
+
+    #[test]
+    fn test_top_rolls_up_remainder_into_other() {
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 5);
+        report.extensions.insert("jpg".to_string(), 3);
+        report.extensions.insert("gif".to_string(), 1);
+        report.mimetypes.insert("image/png".to_string(), 5);
+        report.mimetypes.insert("image/jpeg".to_string(), 3);
+        report.mimetypes.insert("image/gif".to_string(), 1);
+        report.mimetype_sizes.insert("image/png".to_string(), 500);
+        report.mimetype_sizes.insert("image/jpeg".to_string(), 300);
+        report.mimetype_sizes.insert("image/gif".to_string(), 100);
+        let options = DisplayOptions {
+            top: Some(2),
+            ..DisplayOptions::default()
+        };
+
+        let extension_rows = rows(&report.extensions, None, &options);
+        let mimetype_rows = rows(&report.mimetypes, Some(&report.mimetype_sizes), &options);
+
+        let keys: Vec<&str> = extension_rows.iter().map(|row| row.key.as_str()).collect();
+        assert_eq!(keys, ["png", "jpg", "other"]);
+        assert_eq!(extension_rows[2].count, 1);
+        assert_eq!(mimetype_rows[2].size, Some(100));
+
+        for format in [OutputFormat::Text, OutputFormat::Csv, OutputFormat::Json] {
+            let mut out = Vec::new();
+            let options = DisplayOptions {
+                format,
+                other_label: "rest".to_string(),
+                ..options.clone()
+            };
+            report
+                .display(&mut out, &options)
+                .expect("failed to display report");
+            let output = String::from_utf8(out).expect("output is not UTF-8");
+            assert!(output.contains("rest"), "missing rollup row in: {output}");
+            assert!(
+                !output.contains("gif"),
+                "gif should be rolled up in: {output}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rollup_depth_attributes_deep_files_to_ancestor() {
+        let options = ScanOptions {
+            rollup_depth: Some(1),
+            ..ScanOptions::default()
+        };
+
+        let report =
+            scan_entries(fake_tree(), Path::new("t"), None, &options).expect("scan failed");
+
+        let photos = BTreeMap::from([("png".to_string(), 2), ("PNG".to_string(), 1)]);
+        let root = BTreeMap::from([("txt".to_string(), 1)]);
+        assert_eq!(
+            report.rollup,
+            BTreeMap::from([
+                (PathBuf::from("t"), root),
+                (PathBuf::from("t/photos"), photos)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_histogram_scheme_buckets() {
+        let decimal = HistogramScheme {
+            base: 10,
+            buckets: None,
+        };
+        let capped = HistogramScheme {
+            base: 2,
+            buckets: Some(3),
+        };
+        let binary = HistogramScheme {
+            base: 2,
+            buckets: None,
+        };
+
+        assert_eq!(decimal.bucket(0), 0);
+        assert_eq!(decimal.bucket(9), 0);
+        assert_eq!(decimal.bucket(10), 1);
+        assert_eq!(decimal.bucket(999), 2);
+        assert_eq!(decimal.bounds(0), (0, Some(10)));
+        assert_eq!(decimal.bounds(2), (100, Some(1000)));
+        assert_eq!(capped.bucket(1_000_000), 2);
+        assert_eq!(capped.bounds(2), (4, None));
+        assert_eq!(binary.bucket(u64::MAX), 63);
+        assert_eq!(binary.bounds(63), (1 << 63, None));
+    }
+
+    #[test]
+    fn test_size_histogram_counts_sized_files() {
+        let options = ScanOptions {
+            histogram: Some(HistogramScheme {
+                base: 10,
+                buckets: None,
+            }),
+            ..ScanOptions::default()
+        };
+        let entries = vec![
+            FakeEntry::file("t/empty", 1, 0, FileKind::Regular),
+            FakeEntry::file("t/small", 1, 5, FileKind::Regular),
+            FakeEntry::file("t/large", 1, 250, FileKind::Regular),
+            FakeEntry::file("t/link", 1, 250, FileKind::Symlink),
+        ];
+
+        let report = scan_entries(entries, Path::new("t"), None, &options).expect("scan failed");
+
+        assert_eq!(report.size_histogram, vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn test_cold_data_counts_files_touched_before_cutoff() {
+        let cutoff = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let options = ScanOptions {
+            cold_before: Some(cutoff),
+            ..ScanOptions::default()
+        };
+        let old = cutoff - Duration::from_secs(1);
+        let entries = vec![
+            FakeEntry::file("t/old.bin", 1, 300, FileKind::Regular).map(|e| e.touched(old)),
+            FakeEntry::file("t/new.bin", 1, 100, FileKind::Regular).map(|e| e.touched(cutoff)),
+            FakeEntry::file("t/old.lnk", 1, 50, FileKind::Symlink).map(|e| e.touched(old)),
+            FakeEntry::file("t/unknown.bin", 1, 20, FileKind::Regular),
+        ];
+
+        let report = scan_entries(entries, Path::new("t"), None, &options).expect("scan failed");
+        let mut out = Vec::new();
+        report
+            .display_summary_text(
+                &mut out,
+                &DisplayOptions {
+                    cold_data: true,
+                    precision: 0,
+                    ..DisplayOptions::default()
+                },
+            )
+            .expect("failed to write report");
+
+        assert_eq!((report.cold_files, report.cold_size), (1, 300));
+        assert!(
+            String::from_utf8_lossy(&out).contains("cold data: 300 B across 1 file, 71% of total")
+        );
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(
+            parse_age("90d").expect("valid age"),
+            Duration::from_secs(90 * 86_400)
+        );
+        assert_eq!(
+            parse_age("12 H").expect("valid age"),
+            Duration::from_secs(12 * 3600)
+        );
+        assert_eq!(
+            parse_age("1y").expect("valid age"),
+            Duration::from_secs(365 * 86_400)
+        );
+        assert!(parse_age("90").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("1.5y").is_err());
+    }
+
+    #[test]
+    fn test_by_dir_shows_share_of_total() {
+        let mut report = Report {
+            size: 200,
+            ..Report::default()
+        };
+        report.top_level_dirs.insert(
+            PathBuf::from("t/downloads"),
+            DirSizes {
+                shallow_size: 100,
+                recursive_size: 150,
+                files: 3,
+            },
+        );
+        let options = DisplayOptions {
+            by_dir: true,
+            precision: 1,
+            ..DisplayOptions::default()
+        };
+        let mut text = Vec::new();
+        let mut json = Vec::new();
+
+        report.display(&mut text, &options).expect("display failed");
+        report
+            .display(
+                &mut json,
+                &DisplayOptions {
+                    format: OutputFormat::Json,
+                    ..options.clone()
+                },
+            )
+            .expect("display failed");
+        let json: serde_json::Value = serde_json::from_slice(&json).expect("invalid JSON");
+
+        assert!(
+            String::from_utf8_lossy(&text)
+                .contains("t/downloads: 100 B shallow, 150 B recursive (75.0%), 3 files")
+        );
+        assert_eq!(json["by_dir"][0]["pct"], 75.0);
+        assert_eq!(Report::default().share_of_size(0), 0.0);
+    }
+
+    #[test]
+    fn test_by_dir_tracks_shallow_and_recursive_sizes() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::create_dir_all(dir.join("top/nested")).expect("failed to create test dirs");
+        std::fs::create_dir_all(dir.join("empty")).expect("failed to create test dirs");
+        std::fs::write(dir.join("root.txt"), "12345").expect("failed to write file");
+        std::fs::write(dir.join("top/own.txt"), "123").expect("failed to write file");
+        std::fs::write(dir.join("top/nested/deep.txt"), "1234567").expect("failed to write file");
+        let options = ScanOptions {
+            top_level_sizes: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan(dir.clone(), &options).expect("scan failed");
+
+        assert_eq!(
+            report.top_level_dirs.get(&dir.join("top")),
+            Some(&DirSizes {
+                shallow_size: 3,
+                recursive_size: 10,
+                files: 2,
+            })
+        );
+        assert_eq!(
+            report.top_level_dirs.get(&dir.join("empty")),
+            Some(&DirSizes::default())
+        );
+        assert_eq!(report.top_level_dirs.len(), 2);
+
+        let report = scan(dir, &ScanOptions::default()).expect("scan failed");
+        assert!(report.top_level_dirs.is_empty());
+    }
+
+    #[test]
+    fn test_mime_from_ext_trusts_extensions() {
+        let options = ScanOptions {
+            mime_from_ext: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan("testdata".into(), &options).expect("scan failed");
+
+        // The text file masquerading as a PNG is taken at its word
+        assert_eq!(report.mimetypes.get("image/png"), Some(&2));
+        assert_eq!(report.mimetypes.get("text/plain"), Some(&1));
+        assert_eq!(report.mimetypes.values().sum::<i32>(), 27);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_non_utf8_extension_is_counted() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join(OsStr::from_bytes(b"broken.\xffz")), "a")
+            .expect("failed to write file");
+        std::fs::write(dir.join(OsStr::from_bytes(b"\xffname.txt")), "b")
+            .expect("failed to write file");
+
+        let report = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(report.extensions.get("\u{fffd}z"), Some(&1));
+        assert_eq!(report.extensions.get("txt"), Some(&1));
+        assert_eq!(report.extensions.get(""), None);
+        assert_eq!(report.errors.len(), 0);
+    }
+
+    #[test]
+    fn test_collapse_mime_subtypes() {
+        let options = ScanOptions {
+            collapse_mime_subtypes: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan("testdata".into(), &options).expect("scan failed");
+
+        assert_eq!(report.mimetypes.get("image"), Some(&7));
+        assert_eq!(report.mimetypes.get("audio"), Some(&4));
+        assert!(report.mimetypes.keys().all(|key| !key.contains('/')));
+        assert_eq!(report.mimetype_sizes.values().sum::<u64>(), report.size);
+    }
+
+    #[test]
+    fn test_resume_file_continues_after_checkpoint() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        for name in ["a.txt", "b.txt", "c.png"] {
+            std::fs::write(dir.join(name), "data").expect("failed to write file");
+        }
+        let resume_file = dir.join("resume.json");
+        // Pretend an earlier run got as far as b.txt, having seen one extra file
+        let mut partial = Report::default();
+        partial.extensions.insert("txt".to_string(), 3);
+        resume::save(
+            &resume_file,
+            &dir,
+            &dir.join("b.txt"),
+            &partial,
+            &BTreeSet::new(),
+        )
+        .expect("failed to save checkpoint");
+        let options = ScanOptions {
+            resume_file: Some(resume_file.clone()),
+            ..ScanOptions::default()
+        };
+
+        let report = scan(dir.clone(), &options).expect("scan failed");
+
+        // Only c.png and resume.json were left to walk
+        assert_eq!(report.extensions.get("txt"), Some(&3));
+        assert_eq!(report.extensions.get("png"), Some(&1));
+        assert!(
+            !resume_file.exists(),
+            "resume file should be removed on completion"
+        );
+    }
+
+    #[test]
+    fn test_filter_prunes_rejected_directories() {
+        let options = ScanOptions {
+            filter: Some(EntryFilter(Arc::new(|entry| {
+                entry.file_name() != "images" && entry.file_name() != "table.csv"
+            }))),
+            ..ScanOptions::default()
+        };
+
+        let full = scan("testdata".into(), &ScanOptions::default()).expect("scan failed");
+        let filtered = scan("testdata".into(), &options).expect("scan failed");
+
+        assert_eq!(filtered.file_count(), full.file_count() - 8);
+        assert!(
+            !filtered
+                .folders
+                .iter()
+                .any(|dir| dir.starts_with("testdata/images"))
+        );
+        assert_eq!(filtered.extensions.get("csv"), None);
+    }
+
+    #[test]
+    fn test_resume_file_refuses_walk_wide_state() {
+        let temp = test_dir();
+        let resume_file = Some(temp.path().join("resume.json"));
+        let follow_symlinks = ScanOptions {
+            resume_file: resume_file.clone(),
+            follow_symlinks: true,
+            ..ScanOptions::default()
+        };
+        let case_collisions = ScanOptions {
+            resume_file,
+            case_collisions: true,
+            ..ScanOptions::default()
+        };
+
+        let error = scan("testdata".into(), &follow_symlinks).expect_err("should be refused");
+        assert!(error.to_string().contains("--follow-symlinks"));
+        let error = scan("testdata".into(), &case_collisions).expect_err("should be refused");
+        assert!(error.to_string().contains("--case-collisions"));
+    }
+
+    fn report_with_errors() -> Report {
+        let mut report = Report::default();
+        for name in ["a\nb", "c"] {
+            report.push_error(
+                ScanError {
+                    path: PathBuf::from(name),
+                    message: "Permission denied".to_string(),
+                    ..ScanError::default()
+                },
+                Some(2),
+            );
+        }
+        report.push_error(ScanError::default(), Some(2));
+        report
+    }
+
+    #[test]
+    fn test_list_errors() {
+        let report = report_with_errors();
+        let options = DisplayOptions {
+            list_errors: true,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+        report.display(&mut out, &options).expect("display failed");
+        let text = String::from_utf8(out).expect("output is not utf-8");
+        assert!(
+            text.ends_with(
+                "Errors:\na\nb: Permission denied\nc: Permission denied\n... and 1 more\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_dedupe_report_groups_identical_messages() {
+        let mut report = Report::default();
+        for name in ["a", "b", "c", "d"] {
+            report.push_error(
+                ScanError {
+                    path: PathBuf::from(name),
+                    message: format!("failed to open {:?}", PathBuf::from(name)),
+                    kind: ScanErrorKind::PermissionDenied,
+                },
+                None,
+            );
+        }
+        report.push_error(
+            ScanError {
+                path: PathBuf::from("e"),
+                message: "disk on fire".to_string(),
+                ..ScanError::default()
+            },
+            None,
+        );
+        let options = DisplayOptions {
+            list_errors: true,
+            dedupe_errors: true,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report.display(&mut out, &options).expect("display failed");
+
+        let text = String::from_utf8(out).expect("output is not utf-8");
+        assert!(text.ends_with(
+            "Errors: 2 unique messages among 5 errors\n\
+             [permission denied] failed to open <path> (4 paths)\n  a\n  b\n  c\n  ... and 1 more\n\
+             disk on fire (1 path)\n  e\n"
+        ));
+    }
+
+    #[test]
+    fn test_print0_errors() {
+        let report = report_with_errors();
+        let options = DisplayOptions {
+            print0_errors: true,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+        report.display(&mut out, &options).expect("display failed");
+        assert_eq!(out, b"a\nb\0c\0");
+    }
+
+    #[test]
+    fn test_extension_case_counters() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        for name in ["a.JPG", "b.PNG", "c.Jpg", "d.jpg", "e"] {
+            std::fs::write(dir.join(name), "data").expect("failed to write file");
+        }
+
+        let report = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(report.uppercase_ext_files, 2);
+        assert_eq!(report.mixedcase_ext_files, 1);
+    }
+
+    #[test]
+    fn test_include_files_lists_every_file() {
+        let options = ScanOptions {
+            include_files: Some(100),
+            ..ScanOptions::default()
+        };
+        let report = scan("testdata/subfolder".into(), &options).expect("scan failed");
+        let display_options = DisplayOptions {
+            format: OutputFormat::Json,
+            include_files: true,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+        report
+            .display(&mut out, &display_options)
+            .expect("display failed");
+        let json = String::from_utf8(out).expect("output is not utf-8");
+
+        assert_eq!(
+            report.file_records,
+            vec![FileRecord {
+                path: "testdata/subfolder/textfile_masquerading_as.png".into(),
+                ext: "png".to_string(),
+                mime: Some("application/octet-stream".to_string()),
+                size: report.size,
+            }]
+        );
+        assert!(json.contains("\"file_list\": [\n    {\n      \"path\": \"testdata/subfolder/textfile_masquerading_as.png\","));
+    }
+
+    #[test]
+    fn test_include_files_refuses_large_trees() {
+        let options = ScanOptions {
+            include_files: Some(3),
+            ..ScanOptions::default()
+        };
+        assert!(scan("testdata".into(), &options).is_err());
+    }
+
+    #[test]
+    fn test_relative_to_strips_base() {
+        let scan_options = ScanOptions {
+            dir_file_counts: true,
+            ..ScanOptions::default()
+        };
+        let report = scan("testdata".into(), &scan_options).expect("scan failed");
+        let options = DisplayOptions {
+            fat_dirs: Some(1),
+            relative_to: Some("testdata".into()),
+            ..DisplayOptions::default()
+        };
+        let elsewhere = DisplayOptions {
+            relative_to: Some("/elsewhere".into()),
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+        report.display(&mut out, &options).expect("display failed");
+        let text = String::from_utf8(out).expect("output is not utf-8");
+
+        assert!(text.ends_with("Directories with the most files:\ndocuments: 8\n"));
+        assert_eq!(
+            elsewhere.path(Path::new("testdata/images")),
+            Path::new("testdata/images")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_unscannable_kind() {
+        assert_eq!(unscannable_kind(Path::new("testdata")), None);
+        assert_eq!(unscannable_kind(Path::new("testdata/plain.txt")), None);
+        assert_eq!(
+            unscannable_kind(Path::new("/dev/null")),
+            Some("character device")
+        );
+    }
+
+    #[test]
+    fn test_recent_keeps_newest_files() {
+        let mut report = Report::default();
+        for secs in [30, 10, 50, 20, 40] {
+            let file = RecentFile {
+                modified: UNIX_EPOCH + std::time::Duration::from_secs(secs),
+                path: PathBuf::from(format!("{secs}.txt")),
+                size: secs,
+            };
+            report.push_recent(file, 3);
+        }
+
+        let recent: Vec<u64> = report.recent(3).iter().map(|file| file.size).collect();
+
+        assert_eq!(report.recent_files.len(), 3);
+        assert_eq!(recent, vec![50, 40, 30]);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + std::time::Duration::from_secs(1_709_294_400)),
+            "2024-03-01T12:00:00Z"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + std::time::Duration::from_secs(951_825_599)),
+            "2000-02-29T11:59:59Z"
+        );
+    }
+
+    #[test]
+    fn test_friendly_bytes() {
+        assert_eq!(friendly_bytes(0, 2), "0 B");
+        assert_eq!(friendly_bytes(1023, 2), "1023 B");
+        assert_eq!(friendly_bytes(1352, 2), "1.32 KiB");
+        assert_eq!(friendly_bytes(1352, 0), "1 KiB");
+        assert_eq!(friendly_bytes(1352, 3), "1.320 KiB");
+        assert_eq!(friendly_bytes(5 * 1024 * 1024 * 1024, 1), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_heartbeat_message() {
+        let mut report = Report {
+            size: 1352,
+            ..Report::default()
+        };
+        report.extensions.insert("png".to_string(), 2);
+
+        assert_eq!(
+            heartbeat_message(&report),
+            "still scanning: 2 files, 1.32 KiB so far"
+        );
+    }
+
+    #[test]
+    fn test_format_time() {
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(1_709_294_400);
+        let three_days_ago = now - std::time::Duration::from_secs(3 * 86_400 + 5);
+        let soon = now + std::time::Duration::from_secs(90);
+
+        assert_eq!(
+            format_time(three_days_ago, TimeFormat::Iso8601, now),
+            "2024-02-27T11:59:55Z"
+        );
+        assert_eq!(
+            format_time(three_days_ago, TimeFormat::Relative, now),
+            "3 days ago"
+        );
+        assert_eq!(format_time(soon, TimeFormat::Relative, now), "in 1 minute");
+        assert_eq!(format_time(now, TimeFormat::Relative, now), "0 seconds ago");
+        assert_eq!(format_time(now, TimeFormat::Unix, now), "1709294400");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = UNIX_EPOCH + Duration::from_secs(1_709_294_400);
+
+        assert_eq!(
+            parse_timestamp("2024-03-01T12:00:00Z").expect("valid timestamp"),
+            expected
+        );
+        assert_eq!(
+            parse_timestamp("2024-03-01T12:00:00").expect("valid timestamp"),
+            expected
+        );
+        assert_eq!(
+            parse_timestamp("1709294400").expect("valid timestamp"),
+            expected
+        );
+        assert_eq!(
+            parse_timestamp("1970-01-01T00:00:00Z").expect("valid timestamp"),
+            UNIX_EPOCH
+        );
+        assert!(parse_timestamp("2024-13-01T12:00:00Z").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_now_overrides_reference_time() {
+        let now = parse_timestamp("2024-03-01T12:00:00Z").expect("valid timestamp");
+        let options = DisplayOptions {
+            time_format: TimeFormat::Relative,
+            now: Some(now),
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(
+            options.time(now - Duration::from_secs(3 * 86_400)),
+            "3 days ago"
+        );
+    }
+
+    #[test]
+    fn test_group_regex_groups_rotated_files() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        for name in ["app-2024-01-01.log", "app-2024-01-02.log", "other.log"] {
+            std::fs::write(dir.join(name), "data").expect("failed to write file");
+        }
+        let options = ScanOptions {
+            group_regex: Some(GroupRegex {
+                pattern: Regex::new(r"^(\w+)-\d{4}-\d{2}-\d{2}\.log$").expect("invalid regex"),
+                replacement: "$1-*.log".to_string(),
+            }),
+            ..ScanOptions::default()
+        };
+
+        let report = scan(dir.clone(), &options).expect("scan failed");
+
+        assert_eq!(report.extensions.get("app-*.log"), Some(&2));
+        assert_eq!(report.extensions.get("log"), Some(&1));
+    }
+
+    #[test]
+    fn test_empty_file_has_empty_mimetype() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("empty.bin");
+        std::fs::write(&path, "").expect("failed to write file");
+
+        let (mimetype, _) = detect_mimetype(&path, &ScanOptions::default()).expect("detect failed");
+
+        assert_eq!(mimetype, "inode/x-empty");
+    }
+
+    #[test]
+    fn test_no_mime_fallback_records_unknown_content() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("notes.txt");
+        std::fs::write(&path, "plain text").expect("failed to write file");
+        let strict = ScanOptions {
+            no_mime_fallback: true,
+            ..ScanOptions::default()
+        };
+
+        let (fallback, _) = detect_mimetype(&path, &ScanOptions::default()).expect("detect failed");
+        let (unknown, _) = detect_mimetype(&path, &strict).expect("detect failed");
+
+        assert_eq!(fallback, "application/octet-stream");
+        assert_eq!(unknown, "unknown");
+    }
+
+    #[test]
+    fn test_sniff_grows_window_until_recognized() {
+        let temp = test_dir();
+        let path = temp.path().join("data.bin");
+        std::fs::write(&path, vec![0u8; 20_000]).expect("failed to write file");
+        let adaptive = ScanOptions {
+            sniff_bytes: Some(4096),
+            sniff_max_bytes: Some(16_384),
+            ..ScanOptions::default()
+        };
+        let fixed = ScanOptions {
+            sniff_max_bytes: None,
+            ..adaptive.clone()
+        };
+        // Stands in for a format whose magic only shows after 10000 bytes.
+        let late_magic = |bytes: &[u8]| (bytes.len() >= 10_000).then_some("late");
+
+        for mmap in [false, true] {
+            let adaptive = ScanOptions {
+                mmap,
+                ..adaptive.clone()
+            };
+            let found = sniff(&path, &adaptive, late_magic).expect("sniff failed");
+            let missed = sniff(&path, &fixed, late_magic).expect("sniff failed");
+            let fallback = detect_mimetype(&path, &adaptive).expect("detect failed");
+
+            assert_eq!(found, (Some("late"), 16_384));
+            assert_eq!(missed, (None, 4096));
+            assert_eq!(fallback, (OCTET_STREAM_MIMETYPE.to_string(), 16_384));
+        }
+    }
+
+    #[test]
+    fn test_sniff_turns_panics_into_errors() {
+        let path = Path::new("testdata/plain.txt");
+
+        let result = sniff(path, &ScanOptions::default(), |_| -> Option<()> {
+            panic!("malformed header")
+        });
+
+        let message = format!("{:#}", result.expect_err("panic should become an error"));
+        assert!(message.contains("content detection panicked"));
+        assert!(message.contains("malformed header"));
+    }
+
+    #[test]
+    fn test_scan_survives_malformed_headers() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let headers: [(&str, &[u8]); 7] = [
+            ("cut.png", &[0x89, 0x50, 0x4E]),
+            ("cut.jpg", &[0xFF, 0xD8]),
+            ("cut.zip", b"PK"),
+            ("cut.gz", &[0x1F]),
+            ("cut.mp4", b"\0\0\0\x18ftyp"),
+            ("cut.webp", b"RIFF\xFF\xFF\xFF\xFFWEBP"),
+            ("cut.tar", &[0; 300]),
+        ];
+        for (name, header) in headers {
+            std::fs::write(dir.join(name), header).expect("failed to write file");
+        }
+
+        let report = scan(dir, &ScanOptions::default()).expect("scan failed");
+
+        assert!(report.errors.is_empty());
+        assert_eq!(report.file_count(), headers.len());
+    }
+
+    #[test]
+    fn test_scan_counts_bytes_read_for_sniffing() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join("large.bin"), vec![0u8; SNIFF_WINDOW * 2])
+            .expect("failed to write file");
+        std::fs::write(dir.join("small.txt"), "hello").expect("failed to write file");
+        let extensions_only = ScanOptions {
+            extensions_only: true,
+            ..ScanOptions::default()
+        };
+
+        let sniffed = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+        let skipped = scan(dir, &extensions_only).expect("scan failed");
+
+        // Only the sniff window of the large file is read
+        assert_eq!(sniffed.sniffed_files, 2);
+        assert_eq!(sniffed.bytes_read, SNIFF_WINDOW as u64 + 5);
+        assert_eq!(skipped.sniffed_files, 0);
+        assert_eq!(skipped.bytes_read, 0);
+    }
+
+    #[test]
+    fn test_warn_slow_files_records_files_over_threshold() {
+        let every_file = ScanOptions {
+            warn_slow_files: Some(Duration::ZERO),
+            ..ScanOptions::default()
+        };
+        let no_file = ScanOptions {
+            warn_slow_files: Some(Duration::from_secs(60)),
+            ..ScanOptions::default()
+        };
+
+        let slow = scan("testdata/images".into(), &every_file).expect("scan failed");
+        let fast = scan("testdata/images".into(), &no_file).expect("scan failed");
+
+        assert_eq!(slow.slow_files.len(), 7);
+        assert!(fast.slow_files.is_empty());
+    }
+
+    #[test]
+    fn test_check_integrity_flags_images_without_end_marker() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let png_header: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        let png_end: &[u8] = &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82];
+        std::fs::write(dir.join("whole.png"), [png_header, png_end].concat())
+            .expect("failed to write file");
+        std::fs::write(dir.join("cut.png"), png_header).expect("failed to write file");
+        std::fs::write(dir.join("cut.jpg"), [0xFF, 0xD8, 0xFF, 0xE0, 0x00])
+            .expect("failed to write file");
+        std::fs::write(dir.join("notes.txt"), "no marker").expect("failed to write file");
+        let options = ScanOptions {
+            check_integrity: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan(dir.clone(), &options).expect("scan failed");
+
+        assert_eq!(
+            report.suspect_files.iter().sorted().collect::<Vec<_>>(),
+            vec![&dir.join("cut.jpg"), &dir.join("cut.png")]
+        );
+    }
+
+    #[test]
+    fn test_fix_extensions_renames_contradicted_files() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        let png_header: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        std::fs::write(dir.join("photo.jpg"), png_header).expect("failed to write file");
+        std::fs::write(dir.join("image.png"), png_header).expect("failed to write file");
+        std::fs::write(dir.join("notes.png"), "just text").expect("failed to write file");
+        std::fs::write(dir.join("report.docx"), b"PK\x03\x04").expect("failed to write file");
+        let options = ScanOptions {
+            suggest_extensions: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan(dir.clone(), &options).expect("scan failed");
+        let fixed = fix_extensions(&report.misnamed_files, false);
+
+        assert_eq!(
+            report.misnamed_files,
+            vec![MisnamedFile {
+                path: dir.join("photo.jpg"),
+                suggested_ext: "png".to_string(),
+            }]
+        );
+        assert!(fixed);
+        assert!(dir.join("photo.png").exists());
+        assert!(!dir.join("photo.jpg").exists());
+    }
+
+    #[test]
+    fn test_oneline() {
+        let options = ScanOptions {
+            extensions_only: true,
+            ..ScanOptions::default()
+        };
+        let report = scan("testdata".into(), &options).expect("scan failed");
+        let display_options = DisplayOptions {
+            oneline: true,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report
+            .display(&mut out, &display_options)
+            .expect("display failed");
+
+        assert_eq!(String::from_utf8(out).expect("not utf-8"), "27f 5d 1KiB\n");
+    }
+
+    #[test]
+    fn test_warn_if_thresholds() {
+        let report = scan("testdata".into(), &ScanOptions::default()).expect("scan failed");
+        let png = parse_count_threshold("png:1").expect("failed to parse");
+        let dotted = parse_count_threshold(".png:2").expect("failed to parse");
+        let missing = parse_count_threshold("exe:0").expect("failed to parse");
+
+        assert_eq!(
+            png.violation(&report).as_deref(),
+            Some("2 files with extension \"png\", at most 1 allowed")
+        );
+        assert_eq!(dotted.violation(&report), None);
+        assert_eq!(missing.violation(&report), None);
+        assert!(parse_count_threshold("exe").is_err());
+        assert!(parse_count_threshold("exe:-1").is_err());
+    }
+
+    #[test]
+    fn test_outcome_of_scan() {
+        let mut report = Report::default();
+        assert_eq!(Outcome::of_scan(&report, false), Outcome::Clean);
+
+        report.push_error(
+            ScanError {
+                path: PathBuf::from("locked"),
+                message: "Permission denied".to_string(),
+                kind: ScanErrorKind::PermissionDenied,
+            },
+            None,
+        );
+        assert_eq!(Outcome::of_scan(&report, false), Outcome::ScanErrors);
+        report.truncated = true;
+        assert_eq!(Outcome::of_scan(&report, false), Outcome::Truncated);
+        assert_eq!(Outcome::of_scan(&report, true), Outcome::ThresholdExceeded);
+
+        assert_eq!(Outcome::Truncated.coarse(), Outcome::Clean);
+        assert_eq!(Outcome::ThresholdExceeded.coarse().code(), 1);
+        assert_eq!(Outcome::ThresholdExceeded.code(), 4);
+    }
+
+    #[test]
+    fn test_merge_extensionless_by_magic() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::copy("testdata/images/sample.png", dir.join("d41d8cd9"))
+            .expect("failed to copy file");
+        std::fs::write(dir.join("README"), "plain text").expect("failed to write file");
+        let options = ScanOptions {
+            merge_extensionless_by_magic: true,
+            extensions_only: true,
+            ..ScanOptions::default()
+        };
+
+        let merged = scan(dir.clone(), &options).expect("scan failed");
+        let plain = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(merged.extensions.get("png"), Some(&1));
+        assert_eq!(merged.extensions.get(""), Some(&1));
+        assert_eq!(plain.extensions.get(""), Some(&2));
+    }
+
+    #[test]
+    fn test_report_empty_extensions_separately() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        for name in ["Makefile", "notes.", ".bashrc", "main.rs"] {
+            std::fs::write(dir.join(name), "x").expect("failed to write file");
+        }
+        let options = ScanOptions {
+            split_empty_extensions: true,
+            ..ScanOptions::default()
+        };
+
+        let split = scan(dir.clone(), &options).expect("scan failed");
+        let lumped = scan(dir, &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(split.extensions.get("(none)"), Some(&1));
+        assert_eq!(split.extensions.get("(empty)"), Some(&1));
+        assert_eq!(split.extensions.get("(dotfile)"), Some(&1));
+        assert_eq!(split.extensions.get("rs"), Some(&1));
+        assert_eq!(split.extensions.get(""), None);
+        assert_eq!(lumped.extensions.get(""), Some(&3));
+    }
+
+    #[test]
+    fn test_parallel_scan_matches_serial_scan() {
+        let serial = ScanOptions {
+            include_files: Some(100),
+            ..ScanOptions::default()
+        };
+        let parallel = ScanOptions {
+            threads: 4,
+            ..serial.clone()
+        };
+
+        let serial = scan("testdata".into(), &serial).expect("scan failed");
+        let parallel = scan("testdata".into(), &parallel).expect("scan failed");
+
+        assert_eq!(format!("{parallel:?}"), format!("{serial:?}"));
+    }
+
+    #[test]
+    fn test_display_html_escapes_keys() {
+        let mut report = Report::default();
+        report.extensions.insert("<b>&".to_string(), 2);
+        report.extensions.insert("png".to_string(), 1);
+        let options = DisplayOptions {
+            format: OutputFormat::Html,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report.display(&mut out, &options).expect("display failed");
+
+        let html = String::from_utf8(out).expect("output is not utf-8");
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<p>3 files, 0 folders, 0 B</p>"));
+        assert!(html.contains("<tr><td>&lt;b&gt;&amp;</td><td data-value=\"2\">2</td>"));
+        assert!(html.contains("style=\"width: 50.0%\""));
+        assert!(!html.contains("<b>&"));
+    }
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(html_escape(r#"a<b>&"c'"#), "a&lt;b&gt;&amp;&quot;c&#39;");
+    }
+
+    #[test]
+    fn test_scan_error_kind_classifies_io_errors() {
+        let too_long = anyhow::Error::new(io::Error::from(io::ErrorKind::InvalidFilename))
+            .context("failed to open file");
+        let denied = anyhow::Error::new(io::Error::from(io::ErrorKind::PermissionDenied));
+        let other = anyhow::anyhow!("something else");
+
+        assert_eq!(ScanErrorKind::of(&too_long), ScanErrorKind::PathTooLong);
+        assert_eq!(ScanErrorKind::of(&denied), ScanErrorKind::PermissionDenied);
+        assert_eq!(ScanErrorKind::of(&other), ScanErrorKind::Other);
+    }
+
+    #[test]
+    fn test_errors_text_tags_path_too_long() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        // Linux caps a single path component at 255 bytes.
+        let mut report = Report::default();
+        let long_name = dir.join("a".repeat(300));
+        let error = std::fs::metadata(&long_name).expect_err("name should be too long");
+        report.push_error(
+            ScanError {
+                path: long_name,
+                message: error.to_string(),
+                kind: ScanErrorKind::of_io(&error),
+            },
+            None,
+        );
+        let options = DisplayOptions {
+            list_errors: true,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report
+            .display_errors_text(&mut out, &options)
+            .expect("display failed");
+
+        assert_eq!(report.errors[0].kind, ScanErrorKind::PathTooLong);
+        assert!(String::from_utf8_lossy(&out).contains(": [path too long] "));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_not_counted_in_size() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join("data.bin"), [0u8; 100]).expect("failed to write file");
+        std::os::unix::fs::symlink(dir.join("data.bin"), dir.join("link.bin"))
+            .expect("failed to create symlink");
+
+        let report = scan(dir, &ScanOptions::default()).expect("scan failed");
+
+        assert_eq!(report.extensions.get("bin"), Some(&2));
+        assert_eq!(report.size, 100);
+        assert_eq!(report.excluded_symlinks, 1);
+        assert_eq!(report.excluded_special_files, 0);
+    }
+
+    #[test]
+    fn test_no_summary_prints_only_rows() {
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 2);
+        report.extensions.insert("JPG".to_string(), 1);
+        report.uppercase_ext_files = 1;
+        let options = DisplayOptions {
+            no_summary: true,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report.display(&mut out, &options).expect("display failed");
+
+        assert_eq!(
+            String::from_utf8(out).expect("not utf-8"),
+            "png: 2\nJPG: 1\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_by_owner_sums_sizes_per_uid() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join("a.bin"), [0u8; 100]).expect("failed to write file");
+        std::fs::write(dir.join("b.bin"), [0u8; 50]).expect("failed to write file");
+        let uid = std::fs::metadata(dir.join("a.bin"))
+            .expect("failed to stat file")
+            .uid();
+        let options = ScanOptions {
+            owner_sizes: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan(dir.clone(), &options).expect("scan failed");
+
+        assert_eq!(report.by_owner(), vec![(uid, 150)]);
+
+        let report = scan(dir, &ScanOptions::default()).expect("scan failed");
+        assert!(report.owner_sizes.is_empty());
+    }
+
+    #[test]
+    fn test_display_json_streams_errors() {
+        let mut report = Report::default();
+        for i in 0..3 {
+            report.push_error(
+                ScanError {
+                    path: PathBuf::from(format!("/path/{i}")),
+                    message: "error".to_string(),
+                    ..ScanError::default()
+                },
+                None,
+            );
+        }
+        let options = DisplayOptions {
+            format: OutputFormat::Json,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report.display(&mut out, &options).expect("display failed");
+
+        let json: serde_json::Value = serde_json::from_slice(&out).expect("invalid JSON");
+        assert_eq!(json["errors"].as_array().map(Vec::len), Some(3));
+        assert_eq!(json["errors"][2]["path"], "/path/2");
+    }
+
+    #[test]
+    fn test_display_json_without_errors_is_valid() {
+        let report = Report::default();
+        let options = DisplayOptions {
+            format: OutputFormat::Json,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report.display(&mut out, &options).expect("display failed");
+
+        let json: serde_json::Value = serde_json::from_slice(&out).expect("invalid JSON");
+        assert_eq!(json["errors"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_extension_aliases() {
+        let mut report = Report::default();
+        for ext in ["jpg", "jpeg", "htm", "html", "png", ""] {
+            report.extensions.insert(ext.to_string(), 1);
+        }
+
+        let aliases = report.extension_aliases();
+
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases.get("image/jpeg"), Some(&vec!["jpeg", "jpg"]));
+        assert_eq!(aliases.get("text/html"), Some(&vec!["htm", "html"]));
+    }
+
+    #[test]
+    fn test_strip_components() {
+        assert_eq!(
+            strip_components(Path::new("/home/me/photos/a.jpg"), 2),
+            Path::new("photos/a.jpg")
+        );
+        assert_eq!(
+            strip_components(Path::new("home/me/a.jpg"), 0),
+            Path::new("home/me/a.jpg")
+        );
+        assert_eq!(
+            strip_components(Path::new("/home/me/a.jpg"), 5),
+            Path::new("a.jpg")
+        );
+    }
+
+    #[test]
+    fn test_strip_prefix_components_applies_after_relative_to() {
+        let options = DisplayOptions {
+            relative_to: Some("/data".into()),
+            strip_prefix_components: Some(1),
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(
+            options.path(Path::new("/data/2024/june/a.jpg")),
+            Path::new("june/a.jpg")
+        );
+    }
+
+    #[test]
+    fn test_header_names_targets() {
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 1);
+        let text = DisplayOptions {
+            targets: vec![PathBuf::from("/home/me/Downloads")],
+            ..DisplayOptions::default()
+        };
+        let csv = DisplayOptions {
+            format: OutputFormat::Csv,
+            ..text.clone()
+        };
+        let json = DisplayOptions {
+            format: OutputFormat::Json,
+            ..text.clone()
+        };
+        let render = |options: &DisplayOptions| {
+            let mut out = Vec::new();
+            report.display(&mut out, options).expect("display failed");
+            String::from_utf8(out).expect("not utf-8")
+        };
+
+        assert!(render(&text).starts_with("Scan of /home/me/Downloads:\n1 file,"));
+        assert!(render(&csv).starts_with("# target: /home/me/Downloads\nextension,count\n"));
+        let value: serde_json::Value = serde_json::from_str(&render(&json)).expect("invalid JSON");
+        assert_eq!(value["targets"], serde_json::json!(["/home/me/Downloads"]));
+    }
+
+    #[test]
+    fn test_bars_scale_to_largest_count() {
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 4);
+        report.extensions.insert("jpeg".to_string(), 1);
+        let options = DisplayOptions {
+            no_summary: true,
+            bars: Some(24),
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report.display(&mut out, &options).expect("display failed");
+
+        // 24 columns minus key, count and separators leave 15 for the bars.
+        assert_eq!(
+            String::from_utf8(out).expect("not utf-8"),
+            format!(
+                "png   {}  4\njpeg  {}{}  1\n",
+                "█".repeat(15),
+                "█".repeat(4),
+                " ".repeat(11)
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_symlink_is_a_folder_only_when_followed() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::create_dir(dir.join("real")).expect("failed to create dir");
+        std::fs::write(dir.join("real/a.txt"), "hello").expect("failed to write file");
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("link"))
+            .expect("failed to create symlink");
+        let follow = ScanOptions {
+            follow_symlinks: true,
+            ..ScanOptions::default()
+        };
+
+        let unfollowed = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+        let followed = scan(dir, &follow).expect("scan failed");
+
+        assert_eq!(unfollowed.folders.len(), 1);
+        assert_eq!(unfollowed.extensions.get("txt"), Some(&1));
+        assert_eq!(unfollowed.extensions.get(""), Some(&1));
+        assert_eq!(unfollowed.mimetypes.get(DIRECTORY_MIMETYPE), Some(&1));
+        assert_eq!(followed.folders.len(), 2);
+        // The linked tree's files are the same files, so they're only counted once.
+        assert_eq!(followed.extensions.get("txt"), Some(&1));
+        assert_eq!(followed.collapsed_link_paths, 1);
+        assert_eq!(followed.size, 5);
+    }
+
+    /// Links `link` to the directory `target`: a symlink on Unix, a junction on Windows, where
+    /// creating symlinks takes extra privileges.
+    #[cfg(any(unix, windows))]
+    fn link_dir(target: &Path, link: &Path) {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, link).expect("failed to create symlink");
+        #[cfg(windows)]
+        {
+            let status = std::process::Command::new("cmd")
+                .args(["/C", "mklink", "/J"])
+                .arg(link)
+                .arg(target)
+                .stdout(std::process::Stdio::null())
+                .status()
+                .expect("failed to run mklink");
+            assert!(status.success(), "mklink /J failed");
+        }
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn test_directory_link_loop_is_an_error_only_when_followed() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::create_dir(dir.join("real")).expect("failed to create dir");
+        std::fs::write(dir.join("real/a.txt"), "hello").expect("failed to write file");
+        link_dir(&dir.join("real"), &dir.join("real/loop"));
+        let follow = ScanOptions {
+            follow_symlinks: true,
+            ..ScanOptions::default()
+        };
+
+        let unfollowed = scan(dir.clone(), &ScanOptions::default()).expect("scan failed");
+        let followed = scan(dir, &follow).expect("scan failed");
+
+        assert_eq!(unfollowed.excluded_symlinks, 1);
+        assert_eq!(unfollowed.error_count(), 0);
+        assert_eq!(followed.extensions.get("txt"), Some(&1));
+        assert_eq!(followed.error_count(), 1);
+        assert!(followed.errors[0].message.contains("loop"));
+    }
+
+    #[test]
+    fn test_csv_columns_pick_and_order() {
+        let mut report = Report::default();
+        report.mimetypes.insert("image/png".to_string(), 3);
+        report.mimetypes.insert("text/plain".to_string(), 1);
+        report.mimetype_sizes.insert("image/png".to_string(), 300);
+        report.mimetype_sizes.insert("text/plain".to_string(), 7);
+        let options = DisplayOptions {
+            format: OutputFormat::Csv,
+            use_mime: true,
+            csv_columns: Some(vec![CsvColumn::Pct, CsvColumn::Count, CsvColumn::Bytes]),
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
+
+        report.display(&mut out, &options).expect("display failed");
+
+        assert_eq!(
+            String::from_utf8(out).expect("not utf-8"),
+            "mimetype,pct,count,bytes\nimage/png,75.00,3,300\ntext/plain,25.00,1,7\n"
+        );
+    }
+
+    #[test]
+    fn test_display_csv_without_header_row() {
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 2);
+        let options = DisplayOptions {
+            format: OutputFormat::Csv,
+            no_csv_header: true,
+            ..DisplayOptions::default()
+        };
+        let with_columns = DisplayOptions {
+            csv_columns: Some(vec![CsvColumn::Count, CsvColumn::Pct]),
+            ..options.clone()
+        };
+        let render = |options: &DisplayOptions| {
+            let mut out = Vec::new();
+            report.display(&mut out, options).expect("display failed");
+            String::from_utf8(out).expect("not utf-8")
+        };
+
+        assert_eq!(render(&options), "png,2\n");
+        assert_eq!(render(&with_columns), "png,2,100.00\n");
+    }
+
+    #[test]
+    fn test_csv_columns_reject_unknown_names() {
+        let parsed = Cli::try_parse_from(["sumdir", "dir", "--csv-columns", "count,color"]);
+
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn test_output_defaults_to_environment() {
+        let output = |args: &[&str]| {
+            let cli = Cli::try_parse_from(args).expect("failed to parse arguments");
+            cli.scan.expect("expected scan arguments").output
+        };
+
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: every other test that reads SUMDIR_OUTPUT waits for `ENV_LOCK`.
+        unsafe { std::env::set_var("SUMDIR_OUTPUT", "json") };
+        let from_env = output(&["sumdir", "dir"]);
+        let explicit = output(&["sumdir", "dir", "-o", "csv"]);
+        unsafe { std::env::remove_var("SUMDIR_OUTPUT") };
+
+        assert!(matches!(from_env, OutputFormat::Json));
+        assert!(matches!(explicit, OutputFormat::Csv));
+        assert!(matches!(output(&["sumdir", "dir"]), OutputFormat::Text));
+    }
+
+    #[test]
+    fn test_oneline_ignores_output_from_environment() {
+        let check = |args: &[&str]| {
+            let matches = Cli::command()
+                .try_get_matches_from(args)
+                .expect("failed to parse arguments");
+            let cli = Cli::from_arg_matches(&matches).expect("failed to parse arguments");
+            check_oneline(&cli.scan.expect("expected scan arguments"), &matches)
+        };
+
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: every other test that reads SUMDIR_OUTPUT waits for `ENV_LOCK`.
+        unsafe { std::env::set_var("SUMDIR_OUTPUT", "json") };
+        let from_env = check(&["sumdir", "dir", "--oneline"]);
+        let explicit = check(&["sumdir", "dir", "--oneline", "-o", "csv"]);
+        unsafe { std::env::remove_var("SUMDIR_OUTPUT") };
+
+        assert!(from_env.is_ok());
+        assert!(explicit.is_err());
+    }
+
+    /// An entry that only exists in memory, so `scan_entries` can run without a filesystem.
+    struct FakeEntry {
+        path: PathBuf,
+        depth: usize,
+        /// `None` makes the entry a directory.
+        file: Option<FileInfo>,
+    }
+
+    impl FakeEntry {
+        fn dir(path: &str, depth: usize) -> Result<Self, ScanError> {
+            Ok(Self {
+                path: PathBuf::from(path),
+                depth,
+                file: None,
+            })
+        }
+
+        fn file(path: &str, depth: usize, len: u64, kind: FileKind) -> Result<Self, ScanError> {
+            let ext = Path::new(path)
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            Ok(Self {
+                path: PathBuf::from(path),
+                depth,
+                file: Some(FileInfo {
+                    ext_key: ext.clone(),
+                    ext,
+                    len,
+                    kind,
+                    ..FileInfo::default()
+                }),
+            })
+        }
+
+        /// Sets the modification and access time of a file entry.
+        fn touched(mut self, time: SystemTime) -> Self {
+            if let Some(file) = &mut self.file {
+                file.modified = Some(time);
+                file.accessed = Some(time);
+            }
+            self
+        }
+    }
+
+    impl ScanEntry for FakeEntry {
+        fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn depth(&self) -> usize {
+            self.depth
+        }
+
+        fn is_dir(&self) -> bool {
+            self.file.is_none()
+        }
+
+        fn inspect(&self, _report: &mut Report, _options: &ScanOptions) -> Result<FileInfo> {
+            self.file.clone().context("directories can't be inspected")
         }
     }
 
-    fn display_csv(&self, data: &BTreeMap<String, i32>, use_mime: bool) {
-        let header = if use_mime { "mimetype" } else { "extension" };
-        println!("{header},count");
-        for (key, count) in data.iter().sorted_by(|a, b| b.1.cmp(a.1)) {
-            println!("{key},{count}");
+    impl EntrySource for Vec<Result<FakeEntry, ScanError>> {
+        type Entry = FakeEntry;
+
+        fn entries(self) -> impl Iterator<Item = Result<FakeEntry, ScanError>> {
+            self.into_iter()
         }
     }
 
-    fn display_json(&self, data: &BTreeMap<String, i32>, use_mime: bool) {
-        let num_files: i32 = data.values().sum();
-        let key_name = if use_mime { "mimetypes" } else { "extensions" };
-        let entries: Vec<String> = data
-            .iter()
-            .sorted_by(|a, b| b.1.cmp(a.1))
-            .map(|(key, count)| format!("    \"{key}\": {count}"))
-            .collect();
-        let error_entries: Vec<String> = self
-            .errors
-            .iter()
-            .map(|e| {
-                format!(
-                    "    {{\n      \"path\": \"{}\",\n      \"message\": \"{}\"\n    }}",
-                    e.path.display(),
-                    e.message.replace('\\', "\\\\").replace('"', "\\\"")
-                )
-            })
-            .collect();
-        println!("{{");
-        println!("  \"files\": {num_files},");
-        println!("  \"folders\": {},", self.folders.len());
-        println!("  \"size\": {},", self.size);
-        println!("  \"{key_name}\": {{");
-        println!("{}", entries.join(",\n"));
-        println!("  }},");
-        println!("  \"errors\": [");
-        println!("{}", error_entries.join(",\n"));
-        println!("  ]");
-        println!("}}");
-    }
-}
-
-fn detect_mimetype(path: &std::path::Path) -> Result<String> {
-    let mut buffer = [0u8; 8192];
-    let mut file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
-    let bytes_read = file
-        .read(&mut buffer)
-        .with_context(|| format!("failed to read {:?}", path))?;
-    if let Some(kind) = infer::get(&buffer[..bytes_read]) {
-        return Ok(kind.mime_type().to_string());
+    fn fake_tree() -> Vec<Result<FakeEntry, ScanError>> {
+        vec![
+            FakeEntry::dir("t/photos", 1),
+            FakeEntry::file("t/photos/a.png", 2, 100, FileKind::Regular),
+            FakeEntry::dir("t/photos/raw", 2),
+            FakeEntry::file("t/photos/raw/b.PNG", 3, 50, FileKind::Regular),
+            FakeEntry::file("t/photos/link.png", 2, 100, FileKind::Symlink),
+            Err(ScanError {
+                path: PathBuf::from("t/locked"),
+                message: "failed to read entry: Permission denied".to_string(),
+                kind: ScanErrorKind::PermissionDenied,
+            }),
+            FakeEntry::file("t/notes.txt", 1, 7, FileKind::Regular),
+        ]
     }
-    Ok("application/octet-stream".to_string())
-}
 
-fn process_entry(entry: &walkdir::DirEntry, report: &mut Report) -> Result<()> {
-    let ext = entry
-        .path()
-        .extension()
-        .unwrap_or_default()
-        .to_owned()
-        .into_string()
-        .unwrap_or_default();
+    #[test]
+    fn test_depth_counts_tally_extensions_by_depth() {
+        let options = ScanOptions {
+            depth_counts: true,
+            ..ScanOptions::default()
+        };
+        let entries = vec![
+            FakeEntry::file("t/a.png", 1, 0, FileKind::Regular),
+            FakeEntry::file("t/x/b.png", 2, 0, FileKind::Regular),
+            FakeEntry::file("t/x/c.png", 2, 0, FileKind::Regular),
+            FakeEntry::file("t/x/y/z/d.txt", 4, 0, FileKind::Regular),
+        ];
 
-    let metadata = entry
-        .path()
-        .metadata()
-        .with_context(|| format!("failed to read metadata for {:?}", entry.path()))?;
+        let report = scan_entries(entries, Path::new("t"), None, &options).expect("scan failed");
 
-    report.size += metadata.len();
-    report
-        .extensions
-        .entry(ext)
-        .and_modify(|e| *e += 1)
-        .or_insert(1);
+        assert_eq!(
+            report.depth_spread(5),
+            vec![("png", &[1, 2][..]), ("txt", &[0, 0, 0, 1][..])]
+        );
+    }
 
-    let mimetype = detect_mimetype(entry.path())
-        .with_context(|| format!("failed to detect mimetype for {:?}", entry.path()))?;
+    #[test]
+    fn test_sparkline_scales_to_largest_count() {
+        assert_eq!(sparkline(&[1, 8, 0, 4]), "▁█ ▄");
+        assert_eq!(sparkline(&[1, 100]), "▁█");
+        assert_eq!(sparkline(&[]), "");
+    }
 
-    report
-        .mimetypes
-        .entry(mimetype)
-        .and_modify(|e| *e += 1)
-        .or_insert(1);
+    #[test]
+    fn test_top_names_tallies_file_names() {
+        let options = ScanOptions {
+            name_counts: true,
+            ..ScanOptions::default()
+        };
+        let entries = vec![
+            FakeEntry::file("t/a/index.js", 2, 10, FileKind::Regular),
+            FakeEntry::file("t/b/index.js", 2, 30, FileKind::Regular),
+            FakeEntry::file("t/b/main.js", 2, 5, FileKind::Regular),
+        ];
 
-    Ok(())
-}
+        let report = scan_entries(entries, Path::new("t"), None, &options).expect("scan failed");
 
-fn scan(target: PathBuf, progress_bar: bool) -> Report {
-    let mut report = Report::default();
+        assert_eq!(report.top_names(1), vec![("index.js", 2, 40)]);
+        assert_eq!(report.file_names.len(), 2);
+    }
 
-    let pb = if progress_bar {
-        let progress = ProgressBar::new_spinner();
-        progress.set_style(
-            ProgressStyle::default_spinner()
-                .template("{spinner:.green} [{elapsed}] {wide_msg}")
-                .expect("failed to set progress style"),
-        );
-        progress.set_message("Scanning target...");
-        Some(progress)
-    } else {
-        None
-    };
+    #[test]
+    fn test_largest_dirs_counts_entries_recursively() {
+        let options = ScanOptions {
+            dir_entry_counts: true,
+            ..ScanOptions::default()
+        };
 
-    for entry in WalkDir::new(target).into_iter().skip(1) {
-        match entry {
-            Ok(entry) => {
-                if entry.path().is_dir() {
-                    report.folders.push(entry.path().to_path_buf());
-                } else {
-                    if let Some(ref progress) = pb {
-                        progress.set_message(format!("Processing: {}", entry.path().display()));
-                        progress.tick();
-                    }
+        let report =
+            scan_entries(fake_tree(), Path::new("t"), None, &options).expect("scan failed");
 
-                    if let Err(e) = process_entry(&entry, &mut report) {
-                        report.errors.push(ScanError {
-                            path: entry.path().to_path_buf(),
-                            message: e.to_string(),
-                        });
-                    }
-                }
-            }
-            Err(e) => {
-                let path = e.path().map(|p| p.to_path_buf()).unwrap_or_default();
-                report.errors.push(ScanError {
-                    path,
-                    message: format!("failed to read entry: {e}"),
-                });
-            }
-        }
+        assert_eq!(
+            report.largest_dirs(2),
+            vec![
+                (&PathBuf::from("t/photos"), 4),
+                (&PathBuf::from("t/photos/raw"), 1)
+            ]
+        );
+        // The target itself isn't a candidate
+        assert_eq!(report.dir_entry_counts.get(Path::new("t")), None);
     }
 
-    if let Some(progress) = pb {
-        progress.finish_with_message(format!("Completed with {} errors", report.errors.len()));
-    }
+    #[test]
+    fn test_unreadable_directory_is_reported_once() {
+        let denied = |path: &str| ScanError {
+            path: PathBuf::from(path),
+            message: "Permission denied".to_string(),
+            kind: ScanErrorKind::PermissionDenied,
+        };
+        let entries = vec![
+            FakeEntry::dir("t/locked", 1),
+            Err(denied("t/locked")),
+            FakeEntry::file("t/locked/a.txt", 2, 1, FileKind::Regular),
+            Err(denied("t/locked/deep")),
+            FakeEntry::file("t/notes.txt", 1, 7, FileKind::Regular),
+        ];
 
-    report
-}
+        let report = scan_entries(entries, Path::new("t"), None, &ScanOptions::default())
+            .expect("scan failed");
 
-fn main() {
-    let cli = Cli::parse();
-    if !cli.target.exists() {
-        eprintln!(
-            "{} does not exist",
-            cli.target.to_str().unwrap_or("<invalid path>")
-        );
-        std::process::exit(1);
+        assert_eq!(report.error_count(), 1);
+        assert_eq!(report.errors[0].path, PathBuf::from("t/locked"));
+        assert_eq!(report.file_count(), 1);
     }
-    let report = scan(cli.target, cli.progress_bar);
-    report.display(&cli.output, cli.mime);
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_scan_entries_tallies_fake_entries() {
+        let options = ScanOptions {
+            top_level_sizes: true,
+            ..ScanOptions::default()
+        };
+
+        let report =
+            scan_entries(fake_tree(), Path::new("t"), None, &options).expect("scan failed");
+
+        assert_eq!(report.extensions.get("png"), Some(&2));
+        assert_eq!(report.extensions.get("PNG"), Some(&1));
+        assert_eq!(report.extensions.get("txt"), Some(&1));
+        assert_eq!(report.folders.len(), 2);
+        assert_eq!(report.size, 157);
+        assert_eq!(report.excluded_symlinks, 1);
+        assert_eq!(report.uppercase_ext_files, 1);
+        assert_eq!(report.errors[0].kind, ScanErrorKind::PermissionDenied);
+        let photos = &report.top_level_dirs[Path::new("t/photos")];
+        assert_eq!(photos.shallow_size, 100);
+        assert_eq!(photos.recursive_size, 150);
+        assert_eq!(photos.files, 3);
+    }
 
     #[test]
-    fn test_with_testdata_folder() {
-        let report = scan("testdata".into(), false);
-        let num_files: i32 = report.extensions.values().sum();
-        assert_eq!(num_files, 27);
-        assert_eq!(report.folders.len(), 5);
-        assert_eq!(report.errors.len(), 0);
-        // Verify some expected extensions
-        assert_eq!(report.extensions.get("png"), Some(&2)); // sample.png + masquerading.png
-        assert_eq!(report.extensions.get("pdf"), Some(&1));
-        assert_eq!(report.extensions.get("jpg"), Some(&1));
-        assert_eq!(report.extensions.get("docx"), Some(&1));
+    fn test_scan_entries_in_parallel_matches_serial() {
+        let options = ScanOptions {
+            threads: 4,
+            ..ScanOptions::default()
+        };
+
+        let serial = scan_entries(fake_tree(), Path::new("t"), None, &ScanOptions::default())
+            .expect("scan failed");
+        let parallel =
+            scan_entries(fake_tree(), Path::new("t"), None, &options).expect("scan failed");
+
+        assert_eq!(parallel.extensions, serial.extensions);
+        assert_eq!(parallel.size, serial.size);
+        assert_eq!(parallel.folders, serial.folders);
     }
 
     #[test]
-    fn test_detect_mimetype_png() {
-        use std::io::Write;
-        let dir = std::env::temp_dir().join("sumdir_test_png");
-        std::fs::create_dir_all(&dir).expect("failed to create test dir");
-        let file_path = dir.join("test.png");
-        // PNG magic bytes: 89 50 4E 47 0D 0A 1A 0A
-        let png_header: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
-        let mut file = File::create(&file_path).expect("failed to create test file");
-        file.write_all(&png_header)
-            .expect("failed to write test file");
+    fn test_parallel_scan_stops_within_a_batch_of_limit_bytes() {
+        let options = ScanOptions {
+            threads: 2,
+            limit_bytes: Some(10),
+            ..ScanOptions::default()
+        };
+        let paths: Vec<_> = (0..10_000).map(|i| format!("t/{i}.txt")).collect();
+        let entries: Vec<_> = paths
+            .iter()
+            .map(|path| FakeEntry::file(path, 1, 10, FileKind::Regular))
+            .collect();
 
-        assert_eq!(
-            detect_mimetype(&file_path).expect("failed to detect mimetype"),
-            "image/png"
-        );
+        let report = scan_entries(entries, Path::new("t"), None, &options).expect("scan failed");
 
-        std::fs::remove_dir_all(&dir).expect("failed to cleanup test dir");
+        assert!(report.truncated);
+        assert!(report.size <= 10 * 2 * PARALLEL_BATCH_PER_THREAD as u64);
     }
 
     #[test]
-    fn test_detect_mimetype_pdf() {
-        use std::io::Write;
-        let dir = std::env::temp_dir().join("sumdir_test_pdf");
-        std::fs::create_dir_all(&dir).expect("failed to create test dir");
-        let file_path = dir.join("test.pdf");
-        // PDF magic bytes: %PDF
-        let mut file = File::create(&file_path).expect("failed to create test file");
-        file.write_all(b"%PDF-1.4")
-            .expect("failed to write test file");
+    fn test_scan_entries_strict_stops_at_error() {
+        let options = ScanOptions {
+            strict: true,
+            ..ScanOptions::default()
+        };
 
-        assert_eq!(
-            detect_mimetype(&file_path).expect("failed to detect mimetype"),
-            "application/pdf"
-        );
+        let result = scan_entries(fake_tree(), Path::new("t"), None, &options);
 
-        std::fs::remove_dir_all(&dir).expect("failed to cleanup test dir");
+        assert!(result.is_err());
     }
 
     #[test]
-    fn test_detect_mimetype_unknown() {
-        use std::io::Write;
-        let dir = std::env::temp_dir().join("sumdir_test_unknown");
-        std::fs::create_dir_all(&dir).expect("failed to create test dir");
-        let file_path = dir.join("test.txt");
-        let mut file = File::create(&file_path).expect("failed to create test file");
-        file.write_all(b"Hello, world!")
-            .expect("failed to write test file");
+    fn test_case_collisions_between_siblings() {
+        let options = ScanOptions {
+            case_collisions: true,
+            ..ScanOptions::default()
+        };
+        let entries = vec![
+            FakeEntry::dir("t/docs", 1),
+            FakeEntry::file("t/docs/Readme.md", 2, 1, FileKind::Regular),
+            FakeEntry::dir("t/docs/readme.md.d", 2),
+            FakeEntry::file("t/docs/readme.md.d/README.md", 3, 1, FileKind::Regular),
+            FakeEntry::file("t/docs/README.md", 2, 1, FileKind::Regular),
+            FakeEntry::file("t/README.md", 1, 1, FileKind::Regular),
+            FakeEntry::dir("t/Docs", 1),
+        ];
+
+        let report = scan_entries(entries, Path::new("t"), None, &options).expect("scan failed");
 
         assert_eq!(
-            detect_mimetype(&file_path).expect("failed to detect mimetype"),
-            "application/octet-stream"
+            report.case_collisions,
+            vec![
+                vec![PathBuf::from("t/Docs"), PathBuf::from("t/docs")],
+                vec![
+                    PathBuf::from("t/docs/README.md"),
+                    PathBuf::from("t/docs/Readme.md")
+                ],
+            ]
         );
-
-        std::fs::remove_dir_all(&dir).expect("failed to cleanup test dir");
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_scan_populates_mimetypes() {
-        use std::io::Write;
-        let dir = std::env::temp_dir().join("sumdir_test_scan_mime");
-        std::fs::create_dir_all(&dir).expect("failed to create test dir");
+    fn test_exclude_dir_applies_to_followed_link_targets() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::create_dir_all(dir.join("node_modules/pkg")).expect("failed to create dir");
+        std::fs::write(dir.join("node_modules/pkg/index.js"), "x").expect("failed to write");
+        std::fs::write(dir.join("app.js"), "x").expect("failed to write file");
+        std::os::unix::fs::symlink(dir.join("node_modules/pkg"), dir.join("vendored"))
+            .expect("failed to create symlink");
+        let options = ScanOptions {
+            follow_symlinks: true,
+            exclude_dirs: vec!["node_modules".into()],
+            ..ScanOptions::default()
+        };
 
-        // Create a PNG file
-        let png_path = dir.join("image.png");
-        let png_header: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
-        File::create(&png_path)
-            .expect("failed to create png")
-            .write_all(&png_header)
-            .expect("failed to write png");
+        let report = scan(dir, &options).expect("scan failed");
 
-        // Create a PDF file
-        let pdf_path = dir.join("doc.pdf");
-        File::create(&pdf_path)
-            .expect("failed to create pdf")
-            .write_all(b"%PDF-1.4")
-            .expect("failed to write pdf");
+        assert_eq!(report.extensions.get("js"), Some(&1));
+        assert!(report.folders.is_empty());
+    }
 
-        // Create a text file (unknown to infer)
-        let txt_path = dir.join("readme.txt");
-        File::create(&txt_path)
-            .expect("failed to create txt")
-            .write_all(b"Hello")
-            .expect("failed to write txt");
+    #[test]
+    fn test_exclude_dir_skips_matching_directories() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::create_dir_all(dir.join("src/target/debug")).expect("failed to create dir");
+        std::fs::write(dir.join("src/target/debug/a.o"), "x").expect("failed to write");
+        std::fs::write(dir.join("src/main.rs"), "x").expect("failed to write file");
+        let options = ScanOptions {
+            exclude_dirs: vec!["target".into()],
+            ..ScanOptions::default()
+        };
 
-        let report = scan(dir.clone(), false);
+        let report = scan(dir, &options).expect("scan failed");
 
-        assert_eq!(report.mimetypes.get("image/png"), Some(&1));
-        assert_eq!(report.mimetypes.get("application/pdf"), Some(&1));
-        assert_eq!(report.mimetypes.get("application/octet-stream"), Some(&1));
-        assert_eq!(report.mimetypes.len(), 3);
-        assert_eq!(report.errors.len(), 0);
+        assert_eq!(report.extensions.get("o"), None);
+        assert_eq!(report.extensions.get("rs"), Some(&1));
+        assert_eq!(report.folders.len(), 1);
+    }
+
+    #[test]
+    fn test_exclude_hidden_dirs_keeps_hidden_files() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::create_dir_all(dir.join(".git/objects")).expect("failed to create dir");
+        std::fs::write(dir.join(".git/objects/pack"), "x").expect("failed to write file");
+        std::fs::write(dir.join(".env"), "x").expect("failed to write file");
+        std::fs::write(dir.join("main.rs"), "x").expect("failed to write file");
+        let options = ScanOptions {
+            exclude_hidden_dirs: true,
+            ..ScanOptions::default()
+        };
+
+        let report = scan(dir, &options).expect("scan failed");
 
-        std::fs::remove_dir_all(&dir).expect("failed to cleanup test dir");
+        assert_eq!(report.file_count(), 2);
+        assert_eq!(report.extensions.get("rs"), Some(&1));
+        assert!(report.folders.is_empty());
     }
 
     #[test]
-    fn test_testdata_mimetypes() {
-        let report = scan("testdata".into(), false);
-        // Verify various MIME types are detected correctly
-        assert_eq!(report.mimetypes.get("image/png"), Some(&1));
-        assert_eq!(report.mimetypes.get("image/jpeg"), Some(&1));
-        assert_eq!(report.mimetypes.get("image/gif"), Some(&1));
-        assert_eq!(report.mimetypes.get("application/pdf"), Some(&1));
-        assert_eq!(report.mimetypes.get("audio/mpeg"), Some(&1));
-        // DOCX/XLSX/PPTX detected as zip, DOC/XLS as ole-storage
-        assert_eq!(report.mimetypes.get("application/zip"), Some(&4));
-        assert_eq!(report.mimetypes.get("application/x-ole-storage"), Some(&2));
-        // Text files without magic bytes fall back to octet-stream
-        assert!(
-            report
-                .mimetypes
-                .get("application/octet-stream")
-                .expect("expected octet-stream entries")
-                >= &1
+    fn test_exclusion_names_the_rule() {
+        let temp = test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::create_dir_all(dir.join(".git")).expect("failed to create dir");
+        std::fs::create_dir_all(dir.join("target")).expect("failed to create dir");
+        std::fs::write(dir.join("main.rs"), "x").expect("failed to write file");
+        let options = ScanOptions {
+            exclude_dirs: vec!["target".into()],
+            exclude_hidden_dirs: true,
+            ..ScanOptions::default()
+        };
+
+        let reasons: BTreeMap<_, _> = WalkDir::new(&dir)
+            .into_iter()
+            .map(|entry| entry.expect("failed to walk"))
+            .map(|entry| {
+                (
+                    entry.file_name().to_owned(),
+                    exclusion(&entry, &dir, &options),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            reasons[std::ffi::OsStr::new(".git")].as_deref(),
+            Some("skipped by --exclude-hidden-dirs")
+        );
+        assert_eq!(
+            reasons[std::ffi::OsStr::new("target")].as_deref(),
+            Some("skipped by --exclude-dir \"target\"")
         );
+        assert_eq!(reasons[std::ffi::OsStr::new("main.rs")], None);
     }
 
     #[test]
-    #[cfg(unix)]
-    fn test_scan_unreadable_directory() {
-        use std::os::unix::fs::PermissionsExt;
+    fn test_count_symlinks_as_files_restores_sizes() {
+        let entries = || {
+            vec![
+                FakeEntry::file("t/data.bin", 1, 100, FileKind::Regular),
+                FakeEntry::file("t/link.bin", 1, 100, FileKind::Symlink),
+            ]
+        };
+        let legacy = ScanOptions {
+            count_symlinks_as_files: true,
+            ..ScanOptions::default()
+        };
 
-        let dir = std::env::temp_dir().join("sumdir_test_unreadable");
-        std::fs::create_dir_all(&dir).expect("failed to create test dir");
+        let default = scan_entries(entries(), Path::new("t"), None, &ScanOptions::default())
+            .expect("scan failed");
+        let counted = scan_entries(entries(), Path::new("t"), None, &legacy).expect("scan failed");
 
-        // Create a subdirectory with no read permissions
-        let unreadable_dir = dir.join("no_access");
-        std::fs::create_dir_all(&unreadable_dir).expect("failed to create unreadable dir");
+        assert_eq!(default.size, 100);
+        assert_eq!(default.excluded_symlinks, 1);
+        assert_eq!(counted.size, 200);
+        assert_eq!(counted.excluded_symlinks, 0);
+        assert_eq!(counted.extensions.get("bin"), Some(&2));
+    }
 
-        // Create a file inside the unreadable directory first
-        let hidden_file = unreadable_dir.join("hidden.txt");
-        std::fs::write(&hidden_file, "secret").expect("failed to write hidden file");
+    #[test]
+    fn test_parse_ext_list() {
+        let extensions = parse_ext_list("# images\njpg\n\n  .PNG \n");
 
-        // Remove read permissions from the directory
-        std::fs::set_permissions(&unreadable_dir, std::fs::Permissions::from_mode(0o000))
-            .expect("failed to set permissions");
+        assert_eq!(
+            extensions,
+            BTreeSet::from(["jpg".to_string(), "png".to_string()])
+        );
+    }
 
-        // Create a readable file
-        let readable_file = dir.join("readable.txt");
-        std::fs::write(&readable_file, "hello").expect("failed to write readable file");
+    #[test]
+    fn test_ext_whitelist_ignores_other_files() {
+        let options = ScanOptions {
+            ext_whitelist: Some(BTreeSet::from(["png".to_string()])),
+            ..ScanOptions::default()
+        };
+        let entries = vec![
+            FakeEntry::dir("t/a", 1),
+            FakeEntry::file("t/a/x.png", 2, 10, FileKind::Regular),
+            FakeEntry::file("t/a/y.PNG", 2, 20, FileKind::Regular),
+            FakeEntry::file("t/a/z.txt", 2, 40, FileKind::Regular),
+        ];
 
-        let report = scan(dir.clone(), false);
+        let report = scan_entries(entries, Path::new("t"), None, &options).expect("scan failed");
 
-        // Should have scanned the readable file
-        assert_eq!(report.extensions.get("txt"), Some(&1));
-        // Should have recorded errors for the unreadable directory
-        assert!(
-            !report.errors.is_empty(),
-            "expected errors for unreadable directory"
-        );
-        // Error should contain the path
-        assert!(
-            report
-                .errors
-                .iter()
-                .any(|e| e.path.to_string_lossy().contains("no_access")),
-            "error should contain path to unreadable directory"
-        );
+        assert_eq!(report.extensions.len(), 2);
+        assert_eq!(report.extensions.get("txt"), None);
+        assert_eq!(report.size, 30);
+        assert_eq!(report.folders.len(), 1);
+    }
 
-        // Restore permissions for cleanup
-        std::fs::set_permissions(&unreadable_dir, std::fs::Permissions::from_mode(0o755))
-            .expect("failed to restore permissions");
-        std::fs::remove_dir_all(&dir).expect("failed to cleanup test dir");
+    #[test]
+    fn test_report_counts_when_empty() {
+        let report = Report::default();
+
+        assert!(report.is_empty());
+        assert_eq!(report.file_count(), 0);
+        assert_eq!(report.error_count(), 0);
     }
 
     #[test]
-    fn test_scan_error_contains_path_and_message() {
-        // Test that ScanError properly stores both path and message
-        let error = ScanError {
-            path: PathBuf::from("/some/path/file.txt"),
-            message: "test error message".to_string(),
+    fn test_report_counts_when_populated() {
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 2);
+        report.extensions.insert("txt".to_string(), 3);
+        report.push_error(ScanError::default(), Some(1));
+        report.push_error(ScanError::default(), Some(1));
+        let only_folders = Report {
+            folders: vec![PathBuf::from("a")],
+            ..Report::default()
         };
 
-        assert_eq!(error.path, PathBuf::from("/some/path/file.txt"));
-        assert_eq!(error.message, "test error message");
+        assert!(!report.is_empty());
+        assert_eq!(report.file_count(), 5);
+        assert_eq!(report.error_count(), 2);
+        assert!(!only_folders.is_empty());
     }
 
     #[test]
-    fn test_report_with_errors_displays_count() {
-        let mut report = Report::default();
-        report.errors.push(ScanError {
-            path: PathBuf::from("/path/to/file1.txt"),
-            message: "error 1".to_string(),
-        });
-        report.errors.push(ScanError {
-            path: PathBuf::from("/path/to/file2.txt"),
-            message: "error 2".to_string(),
-        });
+    fn test_display_html_of_empty_report() {
+        let options = DisplayOptions {
+            format: OutputFormat::Html,
+            ..DisplayOptions::default()
+        };
+        let mut out = Vec::new();
 
-        assert_eq!(report.errors.len(), 2);
-        assert_eq!(report.errors[0].path, PathBuf::from("/path/to/file1.txt"));
-        assert_eq!(report.errors[1].path, PathBuf::from("/path/to/file2.txt"));
+        Report::default()
+            .display(&mut out, &options)
+            .expect("display failed");
+
+        let html = String::from_utf8(out).expect("output is not utf-8");
+        assert!(html.contains("<p>No files or folders found.</p>"));
+        assert!(!html.contains("<table"));
     }
 }
@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use walkdir::WalkDir;
+
+use crate::hash::{HashAlgo, hash_file};
+use crate::report_skipped;
+
+#[derive(clap::Args)]
+pub struct ManifestArgs {
+    /// Directory to checksum
+    target: PathBuf,
+
+    /// Checksum algorithm; md5 and sha1 are not collision-resistant and don't protect
+    /// against tampering
+    #[arg(long, value_enum, default_value_t = HashAlgo::Sha256)]
+    hash_algo: HashAlgo,
+}
+
+pub fn run(args: &ManifestArgs) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    let errors = write_manifest(&mut stdout, &args.target, args.hash_algo)?;
+    report_skipped(&errors)
+}
+
+/// Writes one `<digest>  <path>` line per file under `target`, in file name order, so the
+/// output can be checked with `sha256sum -c` and friends.
+///
+/// Entries that can't be read or hashed are left out of the manifest; their errors are
+/// returned. Only failing to write the manifest itself is an error.
+fn write_manifest(
+    out: &mut dyn Write,
+    target: &Path,
+    algo: HashAlgo,
+) -> Result<Vec<anyhow::Error>> {
+    let mut errors = Vec::new();
+    for entry in WalkDir::new(target).sort_by_file_name() {
+        let entry = match entry.context("failed to read entry") {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        match hash_file(entry.path(), algo) {
+            Ok(digest) => writeln!(out, "{digest}  {}", entry.path().display())
+                .context("failed to write manifest")?,
+            Err(e) => errors.push(e),
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_manifest() {
+        let mut out = Vec::new();
+
+        let errors = write_manifest(&mut out, Path::new("testdata/subfolder"), HashAlgo::Md5)
+            .expect("manifest failed");
+
+        let manifest = String::from_utf8(out).expect("output is not utf-8");
+        let digest = hash_file(
+            Path::new("testdata/subfolder/textfile_masquerading_as.png"),
+            HashAlgo::Md5,
+        )
+        .expect("hash failed");
+        assert_eq!(
+            manifest,
+            format!("{digest}  testdata/subfolder/textfile_masquerading_as.png\n")
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_manifest_skips_unreadable_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join("a.txt"), "hello").expect("failed to write file");
+        std::fs::write(dir.join("locked.txt"), "secret").expect("failed to write file");
+        std::fs::set_permissions(dir.join("locked.txt"), PermissionsExt::from_mode(0o000))
+            .expect("failed to set permissions");
+        // Permissions don't stop root, so there is nothing to skip then.
+        if std::fs::File::open(dir.join("locked.txt")).is_ok() {
+            return;
+        }
+        let mut out = Vec::new();
+
+        let errors = write_manifest(&mut out, &dir, HashAlgo::Sha256).expect("manifest failed");
+
+        let manifest = String::from_utf8(out).expect("output is not utf-8");
+        assert_eq!(manifest.lines().count(), 1);
+        assert!(manifest.contains("a.txt"));
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Where user names are looked up. Directory services (LDAP, NIS) aren't consulted, so
+/// their users are shown by uid.
+const PASSWD: &str = "/etc/passwd";
+
+/// Maps the uids of local accounts to their user names; empty if they can't be read.
+pub fn user_names() -> BTreeMap<u32, String> {
+    fs::read_to_string(PASSWD)
+        .map(|contents| parse_passwd(&contents))
+        .unwrap_or_default()
+}
+
+/// Parses `passwd(5)` lines (`name:password:uid:gid:...`), skipping malformed ones.
+fn parse_passwd(contents: &str) -> BTreeMap<u32, String> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
+
+/// The user name for `uid`, falling back to the number itself.
+pub fn display_name(names: &BTreeMap<u32, String>, uid: u32) -> String {
+    names.get(&uid).cloned().unwrap_or_else(|| uid.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_passwd() {
+        let names = parse_passwd(
+            "# comment\nroot:x:0:0:root:/root:/bin/sh\nalice:x:1000:1000::/home/alice:/bin/sh\nbroken\n",
+        );
+
+        assert_eq!(names.len(), 2);
+        assert_eq!(display_name(&names, 1000), "alice");
+        assert_eq!(display_name(&names, 4242), "4242");
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Report;
+
+/// How many entries are walked between two checkpoints.
+pub const CHECKPOINT_INTERVAL: usize = 1000;
+
+/// Progress of an interrupted scan: everything up to and including `last_path` (in walk
+/// order) is already accounted for in `report`.
+#[derive(Debug, Deserialize)]
+pub struct Checkpoint {
+    pub target: PathBuf,
+    pub last_path: PathBuf,
+    pub report: Report,
+    /// Directories already found to be unreadable, whose entries are skipped.
+    #[serde(default)]
+    pub unreadable_dirs: BTreeSet<PathBuf>,
+}
+
+/// Borrowed counterpart of [`Checkpoint`], so saving doesn't need to clone the report.
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    target: &'a Path,
+    last_path: &'a Path,
+    report: &'a Report,
+    unreadable_dirs: &'a BTreeSet<PathBuf>,
+}
+
+/// Loads the checkpoint for `target` from `path`, if there is one.
+///
+/// A checkpoint written for a different target is ignored rather than resumed.
+pub fn load(path: &Path, target: &Path) -> Result<Option<Checkpoint>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    let checkpoint: Checkpoint = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse resume file {:?}", path))?;
+    Ok((checkpoint.target == target).then_some(checkpoint))
+}
+
+/// Writes a checkpoint to `path`, replacing the previous one atomically so an interruption
+/// mid-write never leaves a corrupt resume file behind.
+pub fn save(
+    path: &Path,
+    target: &Path,
+    last_path: &Path,
+    report: &Report,
+    unreadable_dirs: &BTreeSet<PathBuf>,
+) -> Result<()> {
+    let partial = path.with_extension("partial");
+    let checkpoint = CheckpointRef {
+        target,
+        last_path,
+        report,
+        unreadable_dirs,
+    };
+    let contents = serde_json::to_string(&checkpoint).context("failed to serialize checkpoint")?;
+    fs::write(&partial, contents).with_context(|| format!("failed to write {:?}", partial))?;
+    fs::rename(&partial, path).with_context(|| format!("failed to replace {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let temp = crate::test_dir();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("resume.json");
+        let mut report = Report::default();
+        report.extensions.insert("png".to_string(), 3);
+
+        let unreadable_dirs = BTreeSet::from([PathBuf::from("target/locked")]);
+
+        save(
+            &path,
+            Path::new("target"),
+            Path::new("target/b.png"),
+            &report,
+            &unreadable_dirs,
+        )
+        .expect("failed to save checkpoint");
+        let loaded = load(&path, Path::new("target"))
+            .expect("failed to load checkpoint")
+            .expect("checkpoint should exist");
+        let other_target = load(&path, Path::new("elsewhere")).expect("failed to load");
+
+        assert_eq!(loaded.last_path, PathBuf::from("target/b.png"));
+        assert_eq!(loaded.report.extensions.get("png"), Some(&3));
+        assert_eq!(loaded.unreadable_dirs, unreadable_dirs);
+        assert!(other_target.is_none());
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use anyhow::{Context, Result};
+
+/// Arguments of `sumdir version`.
+#[derive(clap::Args)]
+pub struct VersionArgs {
+    /// Print the build details as a JSON object
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+/// What went into this build, as embedded by `build.rs`.
+const BUILD_INFO: [(&str, &str); 4] = [
+    ("version", env!("CARGO_PKG_VERSION")),
+    ("git_hash", env!("GIT_HASH")),
+    ("infer_version", env!("INFER_VERSION")),
+    ("target", env!("TARGET_TRIPLE")),
+];
+
+/// Prints what went into this build to stdout.
+pub fn run(args: &VersionArgs) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    write_build_info(&mut stdout, args.json).context("failed to write build info")
+}
+
+/// Writes one `key: value` line per build detail, or a single JSON object.
+fn write_build_info(out: &mut dyn Write, json: bool) -> io::Result<()> {
+    if json {
+        let info: BTreeMap<_, _> = BUILD_INFO.into_iter().collect();
+        serde_json::to_writer_pretty(&mut *out, &info)?;
+        return writeln!(out);
+    }
+    for (key, value) in BUILD_INFO {
+        writeln!(out, "{key}: {value}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_build_info() {
+        let mut text = Vec::new();
+        let mut json = Vec::new();
+
+        write_build_info(&mut text, false).expect("failed to write text");
+        write_build_info(&mut json, true).expect("failed to write JSON");
+
+        let text = String::from_utf8(text).expect("output is not utf-8");
+        let json: serde_json::Value = serde_json::from_slice(&json).expect("invalid JSON");
+        assert!(text.starts_with(&format!("version: {}\n", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(text.lines().count(), 4);
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["git_hash"].is_string());
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::event::{MetadataKind, ModifyKind};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::{DisplayOptions, OutputFormat, ScanOptions, scan_all};
+
+/// How long the filesystem has to stay quiet before a re-scan is triggered.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches `targets` recursively and re-emits the report after every burst of changes.
+///
+/// Blocks until the watcher shuts down.
+pub fn run(
+    targets: &[PathBuf],
+    options: &ScanOptions,
+    display_options: &DisplayOptions,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context("failed to create filesystem watcher")?;
+    for target in targets {
+        watcher
+            .watch(target, RecursiveMode::Recursive)
+            .with_context(|| format!("failed to watch {:?}", target))?;
+    }
+
+    while let Ok(event) = receiver.recv() {
+        if !is_relevant(&event) {
+            continue;
+        }
+        // Swallow the rest of the burst so a large copy only triggers one re-scan.
+        while receiver.recv_timeout(DEBOUNCE).is_ok() {}
+
+        let report = scan_all(
+            targets,
+            &ScanOptions {
+                progress_bar: false,
+                ..options.clone()
+            },
+        )?;
+        let mut stdout = io::stdout().lock();
+        if let OutputFormat::Text = display_options.format {
+            write!(stdout, "\x1B[2J\x1B[H")?;
+        }
+        report
+            .display(&mut stdout, display_options)
+            .context("failed to write report")?;
+    }
+
+    Ok(())
+}
+
+/// Only content and structure changes warrant a re-scan; the scan itself opens every
+/// file, so access events would otherwise trigger an endless loop.
+fn is_relevant(event: &notify::Result<Event>) -> bool {
+    let Ok(event) = event else {
+        return false;
+    };
+    match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime)) => false,
+        EventKind::Modify(_) => true,
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange};
+
+    #[test]
+    fn test_is_relevant_ignores_access_events() {
+        let access = Ok(Event::new(EventKind::Access(AccessKind::Any)));
+        let atime = Ok(Event::new(EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::AccessTime,
+        ))));
+        assert!(!is_relevant(&access));
+        assert!(!is_relevant(&atime));
+    }
+
+    #[test]
+    fn test_is_relevant_accepts_changes() {
+        let create = Ok(Event::new(EventKind::Create(CreateKind::File)));
+        let write = Ok(Event::new(EventKind::Modify(ModifyKind::Data(
+            DataChange::Content,
+        ))));
+        assert!(is_relevant(&create));
+        assert!(is_relevant(&write));
+    }
+}